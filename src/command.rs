@@ -1,12 +1,12 @@
 use std::{io::Write, iter::FromIterator, path::PathBuf};
 
-use clap::{CommandFactory, Parser};
+use clap::{Args, CommandFactory, Parser};
 
 use crate::{
     config::Config,
     context::{Context, ContextBuilder},
     error::Error,
-    tunnel::TunnelManager,
+    tunnel::{HttpProxyServer, RelayServer, TunnelManager},
 };
 
 #[derive(Debug, Parser)]
@@ -48,7 +48,7 @@ impl Cli {
             let context = ContextBuilder::new()
                 .control_path_directory(config.control_path_directory())
                 .build()?;
-            let manager = config.into_manager();
+            let manager = config.into_manager()?;
             (Some(context), Some(manager))
         };
 
@@ -73,6 +73,32 @@ pub enum Command {
     #[command(about = "Checks whether a tunnel is running")]
     Running { tunnels: Vec<String> },
 
+    #[command(about = "Pulls the image of a container tunnel")]
+    Pull { tunnels: Vec<String> },
+
+    #[command(about = "Builds the image of a container tunnel")]
+    Build { tunnels: Vec<String> },
+
+    #[command(about = "Shows the logs of a tunnel")]
+    Logs {
+        tunnel: String,
+
+        #[arg(long, short = 'f', help = "Keeps printing new log lines")]
+        follow: bool,
+
+        #[arg(long, help = "Number of lines to show from the end of the logs")]
+        tail: Option<usize>,
+    },
+
+    #[command(
+        aliases = &["gc"],
+        about = "Shows containers of tunnels which are no longer in the configuration"
+    )]
+    Orphans {
+        #[arg(long, help = "Removes the orphaned containers")]
+        remove: bool,
+    },
+
     #[command(about = "Starts all available tunnels")]
     StartAll,
 
@@ -85,6 +111,12 @@ pub enum Command {
     #[command(about = "Shows current version")]
     Version,
 
+    #[command(hide = true, about = "Runs the relay of a relay tunnel in the foreground")]
+    Relay(RelayArgs),
+
+    #[command(hide = true, about = "Runs the proxy of an HTTP proxy tunnel in the foreground")]
+    HttpProxy(HttpProxyArgs),
+
     #[command(about = "Generates shell completion")]
     Completions { shell: clap_complete::Shell },
 }
@@ -92,7 +124,10 @@ pub enum Command {
 impl Command {
     #[inline]
     pub const fn is_standalone(&self) -> bool {
-        matches!(self, Self::Version | Self::Completions { .. })
+        matches!(
+            self,
+            Self::Version | Self::Completions { .. } | Self::Relay(_) | Self::HttpProxy(_)
+        )
     }
 
     pub fn run(
@@ -113,6 +148,8 @@ impl Command {
                 clap_complete::generate(shell, &mut app, Cli::app_name(), &mut std::io::stdout());
                 Ok(())
             }
+            (Self::Relay(args), ..) => RelayServer::from(args).run(),
+            (Self::HttpProxy(args), ..) => HttpProxyServer::from(args).run(),
             (Self::ListTunnels, Some(manager), _) => {
                 manager.metadata_list().into_iter().for_each(|tunnel| {
                     let name = tunnel.name;
@@ -146,6 +183,34 @@ impl Command {
                 }
                 Ok(())
             }
+            (Self::Pull { tunnels }, Some(manager), Some(context)) => {
+                for tunnel in &tunnels {
+                    manager.pull(&context, tunnel)?;
+                }
+                Ok(())
+            }
+            (Self::Build { tunnels }, Some(manager), Some(context)) => {
+                for tunnel in &tunnels {
+                    manager.build(&context, tunnel)?;
+                }
+                Ok(())
+            }
+            (Self::Logs { tunnel, follow, tail }, Some(manager), Some(context)) => {
+                manager.logs(&context, &tunnel, follow, tail)
+            }
+            (Self::Orphans { remove }, Some(manager), Some(context)) => {
+                for (runtime, container) in manager.orphans()? {
+                    if remove {
+                        manager.remove_orphan(&context, runtime, &container)?;
+                    } else {
+                        println!(
+                            "{:24}\t{:24}\t{}",
+                            container.name, container.tunnel, container.status
+                        );
+                    }
+                }
+                Ok(())
+            }
             (Self::StartAll, Some(manager), Some(context)) => manager.start_all(&context),
             (Self::StopAll, Some(manager), Some(context)) => manager.stop_all(&context),
             (Self::RestartAll, Some(manager), Some(context)) => manager.restart_all(&context),
@@ -153,3 +218,73 @@ impl Command {
         }
     }
 }
+
+#[derive(Debug, Args)]
+pub struct RelayArgs {
+    #[arg(long)]
+    listen_host: String,
+
+    #[arg(long)]
+    listen_port: u16,
+
+    #[arg(long)]
+    target_host: String,
+
+    #[arg(long)]
+    target_port: u16,
+
+    #[arg(long)]
+    max_connections: usize,
+
+    #[arg(long)]
+    control_socket: PathBuf,
+}
+
+impl From<RelayArgs> for RelayServer {
+    fn from(args: RelayArgs) -> Self {
+        let RelayArgs {
+            listen_host,
+            listen_port,
+            target_host,
+            target_port,
+            max_connections,
+            control_socket,
+        } = args;
+        Self { listen_host, listen_port, target_host, target_port, max_connections, control_socket }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct HttpProxyArgs {
+    #[arg(long)]
+    listen_host: String,
+
+    #[arg(long)]
+    listen_port: u16,
+
+    #[arg(long)]
+    socks_host: String,
+
+    #[arg(long)]
+    socks_port: u16,
+
+    #[arg(long)]
+    max_connections: usize,
+
+    #[arg(long)]
+    control_socket: PathBuf,
+}
+
+impl From<HttpProxyArgs> for HttpProxyServer {
+    fn from(args: HttpProxyArgs) -> Self {
+        let HttpProxyArgs {
+            listen_host,
+            listen_port,
+            socks_host,
+            socks_port,
+            max_connections,
+            control_socket,
+        } = args;
+        Self { listen_host, listen_port, socks_host, socks_port, max_connections, control_socket }
+    }
+}
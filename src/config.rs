@@ -1,13 +1,24 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize};
 use snafu::ResultExt;
 
 use crate::{
     error,
     error::Error,
-    tunnel,
-    tunnel::{DockerOpenVPNTunnel, DockerTunnel, SshTunnel, TunnelManager, TunnelMeta},
+    hash, tunnel,
+    tunnel::{
+        CommandTunnel, ContainerBackend, ContainerDefaults, ContainerOptions, ContainerRuntime,
+        ContainerTarget, DockerEngine, DockerOpenConnectTunnel, DockerOpenVPNTunnel, DockerTunnel,
+        DockerWireGuardTunnel, EnvironmentValue, HttpProxyTunnel, KubernetesTunnel,
+        OpenConnectOptions, OpenVPNOptions, ReadinessProbe, RelayTunnel, SecretSource, SshJumpHost,
+        SshLocalForward, SshRemoteForward, SshTunnel, TorTunnel, TunnelManager, TunnelMeta,
+        WireGuardOptions,
+    },
 };
 
 #[derive(Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
@@ -17,11 +28,15 @@ enum Tunnel {
     Docker {
         name: String,
         description: Option<String>,
+        #[serde(default)]
+        container_runtime: Option<ContainerRuntime>,
         image_name: String,
         container_name: String,
         container_port: u16,
         listen_host: String,
         listen_port: u16,
+        #[serde(flatten)]
+        options: ContainerOptions,
     },
 
     #[serde(rename = "ssh")]
@@ -29,17 +44,36 @@ enum Tunnel {
         name: String,
         description: Option<String>,
         remote_host: String,
-        remote_port: u16,
-        user_name: String,
-        identify_file: PathBuf,
+        #[serde(default)]
+        remote_port: Option<u16>,
+        #[serde(default)]
+        user_name: Option<String>,
+        #[serde(default)]
+        identify_file: Option<PathBuf>,
+        #[serde(default)]
+        identity_passphrase: Option<SecretSource>,
+        #[serde(default)]
+        ssh_config: Option<PathBuf>,
         listen_host: String,
         listen_port: u16,
+        #[serde(default)]
+        local_forwards: Vec<SshLocalForward>,
+        #[serde(default)]
+        remote_forwards: Vec<SshRemoteForward>,
+        #[serde(default)]
+        jump_hosts: Vec<SshJumpHost>,
+        #[serde(default, deserialize_with = "deserialize_ssh_options")]
+        ssh_options: BTreeMap<String, String>,
+        #[serde(default)]
+        extra_args: Vec<String>,
     },
 
     #[serde(rename = "docker-openvpn")]
     DockerOpenVPN {
         name: String,
         description: Option<String>,
+        #[serde(default)]
+        container_runtime: Option<ContainerRuntime>,
         image_name: String,
         container_name: String,
         container_port: u16,
@@ -47,34 +81,225 @@ enum Tunnel {
         listen_port: u16,
         config_file: PathBuf,
         auth_file: Option<PathBuf>,
+        #[serde(default)]
+        auth: Option<SecretSource>,
+        #[serde(flatten)]
+        openvpn: OpenVPNOptions,
+        #[serde(flatten)]
+        options: ContainerOptions,
+    },
+
+    #[serde(rename = "docker-wireguard")]
+    DockerWireGuard {
+        name: String,
+        description: Option<String>,
+        #[serde(default)]
+        container_runtime: Option<ContainerRuntime>,
+        image_name: String,
+        container_name: String,
+        container_port: u16,
+        listen_host: String,
+        listen_port: u16,
+        config_file: PathBuf,
+        #[serde(flatten)]
+        wireguard: WireGuardOptions,
+        #[serde(flatten)]
+        options: ContainerOptions,
+    },
+
+    #[serde(rename = "docker-openconnect")]
+    DockerOpenConnect {
+        name: String,
+        description: Option<String>,
+        #[serde(default)]
+        container_runtime: Option<ContainerRuntime>,
+        image_name: String,
+        container_name: String,
+        container_port: u16,
+        listen_host: String,
+        listen_port: u16,
+        server: String,
+        #[serde(flatten)]
+        openconnect: OpenConnectOptions,
+        #[serde(flatten)]
+        options: ContainerOptions,
+    },
+
+    #[serde(rename = "tor")]
+    Tor {
+        name: String,
+        description: Option<String>,
+        listen_host: String,
+        listen_port: u16,
+        #[serde(default)]
+        bridges: Vec<String>,
+        #[serde(default)]
+        transport_plugins: Vec<String>,
+        #[serde(default)]
+        exit_countries: Vec<String>,
+        #[serde(default, deserialize_with = "deserialize_tor_options")]
+        tor_options: BTreeMap<String, String>,
+        /// Seconds to wait for tor to finish its bootstrap.
+        #[serde(default)]
+        bootstrap_timeout: Option<u64>,
+        #[serde(default)]
+        container: Option<TorContainer>,
+    },
+
+    #[serde(rename = "kubernetes")]
+    Kubernetes {
+        name: String,
+        description: Option<String>,
+        #[serde(default)]
+        kubectl: Option<String>,
+        #[serde(default)]
+        kubeconfig: Option<PathBuf>,
+        #[serde(default)]
+        context: Option<String>,
+        #[serde(default)]
+        namespace: Option<String>,
+        resource: String,
+        listen_host: String,
+        listen_port: u16,
+        remote_port: u16,
+        #[serde(default)]
+        extra_args: Vec<String>,
     },
+
+    #[serde(rename = "command")]
+    Command {
+        name: String,
+        description: Option<String>,
+        command: Vec<String>,
+        #[serde(default)]
+        environment: BTreeMap<String, EnvironmentValue>,
+        #[serde(default)]
+        working_directory: Option<PathBuf>,
+        #[serde(default)]
+        readiness: Option<ReadinessProbe>,
+        /// Seconds to wait for the readiness probe to succeed.
+        #[serde(default)]
+        ready_timeout: Option<u64>,
+    },
+
+    #[serde(rename = "relay")]
+    Relay {
+        name: String,
+        description: Option<String>,
+        listen_host: String,
+        listen_port: u16,
+        target_host: String,
+        target_port: u16,
+        #[serde(default)]
+        max_connections: Option<usize>,
+    },
+
+    #[serde(rename = "http-proxy")]
+    HttpProxy {
+        name: String,
+        description: Option<String>,
+        listen_host: String,
+        listen_port: u16,
+        /// Name of the tunnel providing the SOCKS endpoint.
+        upstream: String,
+        #[serde(default)]
+        max_connections: Option<usize>,
+    },
+}
+
+/// Runs tor in a container instead of as a native daemon.
+#[derive(Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+struct TorContainer {
+    #[serde(default)]
+    container_runtime: Option<ContainerRuntime>,
+    image_name: String,
+    container_name: String,
+    #[serde(flatten)]
+    options: ContainerOptions,
+}
+
+// ssh option values are plain strings to ssh, but YAML users tend to write
+// `ServerAliveInterval: 30` or `Compression: true`.
+fn deserialize_ssh_options<'de, D>(deserializer: D) -> Result<BTreeMap<String, String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_scalar_options(deserializer, "ssh", ("yes", "no"))
+}
+
+// tor spells booleans as 1 and 0
+fn deserialize_tor_options<'de, D>(deserializer: D) -> Result<BTreeMap<String, String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_scalar_options(deserializer, "tor", ("1", "0"))
 }
 
-impl From<Tunnel> for Box<dyn tunnel::Tunnel> {
-    fn from(val: Tunnel) -> Self {
-        match val {
-            Tunnel::Docker {
+fn deserialize_scalar_options<'de, D>(
+    deserializer: D,
+    program: &str,
+    (yes, no): (&str, &str),
+) -> Result<BTreeMap<String, String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    BTreeMap::<String, serde_yaml::Value>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(key, value)| {
+            let value = match value {
+                serde_yaml::Value::String(value) => value,
+                serde_yaml::Value::Number(value) => value.to_string(),
+                serde_yaml::Value::Bool(value) => if value { yes } else { no }.to_owned(),
+                _ => {
+                    return Err(de::Error::custom(format!(
+                        "value of {program} option `{key}` must be a string, number or boolean"
+                    )))
+                }
+            };
+            Ok((key, value))
+        })
+        .collect()
+}
+
+impl Tunnel {
+    // one arm for each tunnel type
+    #[allow(clippy::too_many_lines)]
+    fn into_tunnel(
+        self,
+        defaults: &ContainerDefaults,
+        tunnels: &BTreeMap<String, Box<dyn tunnel::Tunnel>>,
+    ) -> Result<Box<dyn tunnel::Tunnel>, Error> {
+        Ok(match self {
+            Self::Docker {
                 name,
                 description,
+                container_runtime,
                 image_name,
                 container_name,
                 container_port,
                 listen_host,
                 listen_port,
+                options,
             } => {
                 let meta = TunnelMeta { name, description };
-                Box::new(DockerTunnel {
+                Box::new(DockerTunnel::from_options(
                     meta,
-                    image_name,
-                    container_name,
-                    container_port,
-                    listen_host,
-                    listen_port,
-                })
+                    ContainerTarget {
+                        runtime: container_runtime,
+                        image_name,
+                        container_name,
+                        container_port,
+                        listen_host,
+                        listen_port,
+                    },
+                    options,
+                    defaults,
+                ))
             }
-            Tunnel::DockerOpenVPN {
+            Self::DockerOpenVPN {
                 name,
                 description,
+                container_runtime,
                 image_name,
                 container_name,
                 container_port,
@@ -82,27 +307,245 @@ impl From<Tunnel> for Box<dyn tunnel::Tunnel> {
                 listen_port,
                 config_file,
                 auth_file,
+                auth,
+                openvpn,
+                options,
             } => {
+                if auth.is_some() && auth_file.is_some() {
+                    return Err(Error::ConflictingOptions {
+                        tunnel: name,
+                        option: "auth".to_owned(),
+                        other_option: "auth_file".to_owned(),
+                    });
+                }
                 let meta = TunnelMeta { name, description };
-                let docker_tunnel = DockerTunnel {
+                let docker_tunnel = DockerTunnel::from_options(
+                    meta,
+                    ContainerTarget {
+                        runtime: container_runtime,
+                        image_name,
+                        container_name,
+                        container_port,
+                        listen_host,
+                        listen_port,
+                    },
+                    options,
+                    defaults,
+                );
+                Box::new(DockerOpenVPNTunnel {
+                    docker_tunnel,
+                    config_file,
+                    auth_file,
+                    auth,
+                    openvpn,
+                })
+            }
+            Self::DockerWireGuard {
+                name,
+                description,
+                container_runtime,
+                image_name,
+                container_name,
+                container_port,
+                listen_host,
+                listen_port,
+                config_file,
+                wireguard,
+                options,
+            } => {
+                let meta = TunnelMeta { name, description };
+                let docker_tunnel = DockerTunnel::from_options(
+                    meta,
+                    ContainerTarget {
+                        runtime: container_runtime,
+                        image_name,
+                        container_name,
+                        container_port,
+                        listen_host,
+                        listen_port,
+                    },
+                    options,
+                    defaults,
+                );
+                Box::new(DockerWireGuardTunnel { docker_tunnel, config_file, wireguard })
+            }
+            Self::DockerOpenConnect {
+                name,
+                description,
+                container_runtime,
+                image_name,
+                container_name,
+                container_port,
+                listen_host,
+                listen_port,
+                server,
+                openconnect,
+                options,
+            } => {
+                let meta = TunnelMeta { name, description };
+                let docker_tunnel = DockerTunnel::from_options(
+                    meta,
+                    ContainerTarget {
+                        runtime: container_runtime,
+                        image_name,
+                        container_name,
+                        container_port,
+                        listen_host,
+                        listen_port,
+                    },
+                    options,
+                    defaults,
+                );
+                Box::new(DockerOpenConnectTunnel { docker_tunnel, server, openconnect })
+            }
+            Self::Tor {
+                name,
+                description,
+                listen_host,
+                listen_port,
+                bridges,
+                transport_plugins,
+                exit_countries,
+                tor_options,
+                bootstrap_timeout,
+                container,
+            } => {
+                let meta = TunnelMeta { name, description };
+                let container = container.map(|container| {
+                    let target = ContainerTarget {
+                        runtime: container.container_runtime,
+                        image_name: container.image_name,
+                        container_name: container.container_name,
+                        container_port: TorTunnel::CONTAINER_SOCKS_PORT,
+                        listen_host: listen_host.clone(),
+                        listen_port,
+                    };
+                    DockerTunnel::from_options(meta.clone(), target, container.options, defaults)
+                });
+                Box::new(TorTunnel {
+                    meta,
+                    listen_host,
+                    listen_port,
+                    bridges,
+                    transport_plugins,
+                    exit_countries,
+                    tor_options,
+                    bootstrap_timeout: bootstrap_timeout
+                        .map_or(TorTunnel::DEFAULT_BOOTSTRAP_TIMEOUT, Duration::from_secs),
+                    container,
+                })
+            }
+            Self::Kubernetes {
+                name,
+                description,
+                kubectl,
+                kubeconfig,
+                context,
+                namespace,
+                resource,
+                listen_host,
+                listen_port,
+                remote_port,
+                extra_args,
+            } => {
+                let meta = TunnelMeta { name, description };
+                Box::new(KubernetesTunnel {
+                    meta,
+                    kubectl: kubectl.unwrap_or_else(|| "kubectl".to_owned()),
+                    kubeconfig,
+                    kube_context: context,
+                    namespace,
+                    resource,
+                    listen_host,
+                    listen_port,
+                    remote_port,
+                    extra_args,
+                })
+            }
+            Self::Command {
+                name,
+                description,
+                command,
+                environment,
+                working_directory,
+                readiness,
+                ready_timeout,
+            } => {
+                let meta = TunnelMeta { name, description };
+                Box::new(CommandTunnel {
+                    meta,
+                    command,
+                    environment,
+                    working_directory,
+                    readiness,
+                    ready_timeout: ready_timeout
+                        .map_or(CommandTunnel::DEFAULT_READY_TIMEOUT, Duration::from_secs),
+                })
+            }
+            Self::Relay {
+                name,
+                description,
+                listen_host,
+                listen_port,
+                target_host,
+                target_port,
+                max_connections,
+            } => {
+                let meta = TunnelMeta { name, description };
+                Box::new(RelayTunnel {
+                    meta,
+                    listen_host,
+                    listen_port,
+                    target_host,
+                    target_port,
+                    max_connections: max_connections
+                        .unwrap_or(RelayTunnel::DEFAULT_MAX_CONNECTIONS),
+                })
+            }
+            Self::HttpProxy {
+                name,
+                description,
+                listen_host,
+                listen_port,
+                upstream,
+                max_connections,
+            } => {
+                let (socks_host, socks_port) = tunnels
+                    .get(&upstream)
+                    .ok_or_else(|| Error::TunnelNotFound { tunnel: upstream.clone() })?
+                    .socks_endpoint()
+                    .ok_or_else(|| Error::NoSocksEndpoint {
+                        tunnel: name.clone(),
+                        upstream: upstream.clone(),
+                    })?;
+                let meta = TunnelMeta { name, description };
+                Box::new(HttpProxyTunnel {
                     meta,
-                    image_name,
-                    container_name,
-                    container_port,
                     listen_host,
                     listen_port,
-                };
-                Box::new(DockerOpenVPNTunnel { docker_tunnel, config_file, auth_file })
+                    socks_host: socks_host.to_owned(),
+                    socks_port,
+                    upstream,
+                    max_connections: max_connections
+                        .unwrap_or(HttpProxyTunnel::DEFAULT_MAX_CONNECTIONS),
+                })
             }
-            Tunnel::Ssh {
+            Self::Ssh {
                 name,
                 description,
                 remote_host,
                 remote_port,
                 user_name,
                 identify_file,
+                identity_passphrase,
+                ssh_config,
                 listen_host,
                 listen_port,
+                local_forwards,
+                remote_forwards,
+                jump_hosts,
+                ssh_options,
+                extra_args,
             } => {
                 let meta = TunnelMeta { name, description };
                 Box::new(SshTunnel {
@@ -111,18 +554,31 @@ impl From<Tunnel> for Box<dyn tunnel::Tunnel> {
                     remote_port,
                     user_name,
                     identify_file,
+                    identity_passphrase,
+                    ssh_config,
                     listen_host,
                     listen_port,
+                    local_forwards,
+                    remote_forwards,
+                    jump_hosts,
+                    ssh_options,
+                    extra_args,
                 })
             }
-        }
+        })
     }
 }
 
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
 pub struct Config {
     control_path_directory: PathBuf,
+    #[serde(default)]
+    container_runtime: ContainerRuntime,
+    #[serde(default)]
+    container_backend: ContainerBackend,
     tunnels: Vec<Tunnel>,
+    #[serde(skip)]
+    file_id: String,
 }
 
 impl Config {
@@ -136,27 +592,46 @@ impl Config {
         let content = std::fs::read_to_string(&config_file).context({
             error::ReadConfigFileSnafu { file_path: config_file.as_ref().to_owned() }
         })?;
-        Self::from_str(&content)
+        let file_id = file_id(config_file.as_ref());
+        Ok(Self { file_id, ..Self::from_str(&content)? })
     }
 
     #[inline]
     pub fn control_path_directory(&self) -> &Path { &self.control_path_directory }
 
-    pub fn into_manager(self) -> TunnelManager {
-        let tunnels = self
-            .tunnels
-            .into_iter()
-            .map(|tunnel| {
-                let tunnel: Box<dyn tunnel::Tunnel> = tunnel.into();
-                let tunnel_name = tunnel.name().to_string();
-                (tunnel_name, tunnel)
-            })
-            .collect();
+    /// Builds the tunnels, those depending on another tunnel after the others.
+    pub fn into_manager(self) -> Result<TunnelManager, Error> {
+        let container_defaults = ContainerDefaults {
+            runtime: self.container_runtime,
+            engine: match self.container_backend {
+                ContainerBackend::Cli => None,
+                ContainerBackend::Api => DockerEngine::from_env().or_else(|| {
+                    tracing::warn!("DOCKER_HOST is not a unix socket, fall back to the CLI");
+                    None
+                }),
+            },
+            config_id: self.file_id,
+        };
+        let (dependent, independent): (Vec<_>, Vec<_>) =
+            self.tunnels.into_iter().partition(|tunnel| matches!(tunnel, Tunnel::HttpProxy { .. }));
+        let mut tunnels = BTreeMap::new();
+        for tunnel in independent.into_iter().chain(dependent) {
+            let tunnel = tunnel.into_tunnel(&container_defaults, &tunnels)?;
+            let tunnel_name = tunnel.name().to_string();
+            let _unused = tunnels.insert(tunnel_name, tunnel);
+        }
 
-        TunnelManager { tunnels }
+        Ok(TunnelManager { tunnels, container_defaults })
     }
 }
 
+/// Identifies a configuration file by a FNV-1a hash of its path, containers
+/// labelled with another identifier belong to other configuration files.
+fn file_id(config_file: &Path) -> String {
+    let path = config_file.canonicalize().unwrap_or_else(|_| config_file.to_owned());
+    format!("{:016x}", hash::fnv1a(path.to_string_lossy().as_bytes()))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -190,11 +665,13 @@ mod test {
             Some(&Tunnel::Docker {
                 name: "docker-tunnel".to_owned(),
                 description: None,
+                container_runtime: None,
                 image_name: "docker-tunnel".to_owned(),
                 container_name: "docker-tunnel".to_owned(),
                 container_port: 8118,
                 listen_host: "127.0.0.1".to_owned(),
                 listen_port: 3128,
+                options: ContainerOptions::default(),
             })
         );
     }
@@ -222,10 +699,580 @@ mod test {
                 listen_host: "127.0.0.1".to_owned(),
                 listen_port: 8051,
                 remote_host: "www.google.com".to_owned(),
-                remote_port: 26,
-                user_name: "the-user".to_owned(),
-                identify_file: "/tmp/id".into(),
+                remote_port: Some(26),
+                user_name: Some("the-user".to_owned()),
+                identify_file: Some("/tmp/id".into()),
+                identity_passphrase: None,
+                ssh_config: None,
+                local_forwards: Vec::new(),
+                remote_forwards: Vec::new(),
+                jump_hosts: Vec::new(),
+                ssh_options: BTreeMap::new(),
+                extra_args: Vec::new(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_ssh_tunnel_with_local_forwards() {
+        let data = r"
+            control_path_directory: /tmp/tunka
+            tunnels:
+                - type: ssh
+                  name: ssh-tunnel
+                  listen_host: 127.0.0.1
+                  listen_port: 8051
+                  remote_host: bastion.example.com
+                  remote_port: 22
+                  user_name: the-user
+                  identify_file: /tmp/id
+                  local_forwards:
+                    - listen_host: 127.0.0.1
+                      listen_port: 5432
+                      target_host: db.internal
+                      target_port: 5432
+                    - listen_host: 127.0.0.1
+                      listen_port: 6379
+                      target_socket: /run/redis/redis.sock
+            ";
+        let config = Config::from_str(data).unwrap();
+        let Some(Tunnel::Ssh { local_forwards, .. }) = config.tunnels.first() else {
+            panic!("expected ssh tunnel");
+        };
+        assert_eq!(
+            local_forwards.iter().map(SshLocalForward::to_arg).collect::<Vec<_>>(),
+            ["127.0.0.1:5432:db.internal:5432", "127.0.0.1:6379:/run/redis/redis.sock"]
+        );
+    }
+
+    #[test]
+    fn test_ssh_tunnel_with_remote_forwards() {
+        let data = r"
+            control_path_directory: /tmp/tunka
+            tunnels:
+                - type: ssh
+                  name: ssh-tunnel
+                  listen_host: 127.0.0.1
+                  listen_port: 8051
+                  remote_host: staging.example.com
+                  remote_port: 22
+                  user_name: the-user
+                  identify_file: /tmp/id
+                  remote_forwards:
+                    - listen_port: 8080
+                      target_host: 127.0.0.1
+                      target_port: 3000
+            ";
+        let config = Config::from_str(data).unwrap();
+        let Some(Tunnel::Ssh { remote_forwards, .. }) = config.tunnels.first() else {
+            panic!("expected ssh tunnel");
+        };
+        assert_eq!(
+            remote_forwards.iter().map(SshRemoteForward::to_arg).collect::<Vec<_>>(),
+            ["8080:127.0.0.1:3000"]
+        );
+    }
+
+    #[test]
+    fn test_ssh_tunnel_with_jump_hosts() {
+        let data = r"
+            control_path_directory: /tmp/tunka
+            tunnels:
+                - type: ssh
+                  name: ssh-tunnel
+                  listen_host: 127.0.0.1
+                  listen_port: 8051
+                  remote_host: prod.internal
+                  remote_port: 22
+                  user_name: the-user
+                  identify_file: /tmp/id
+                  jump_hosts:
+                    - host: bastion.example.com
+                      user_name: jump
+                      identify_file: /tmp/jump_id
+                    - host: inner-bastion
+                      port: 2222
+            ";
+        let config = Config::from_str(data).unwrap();
+        let Some(Tunnel::Ssh { jump_hosts, .. }) = config.tunnels.first() else {
+            panic!("expected ssh tunnel");
+        };
+        assert_eq!(
+            jump_hosts,
+            &[
+                SshJumpHost {
+                    host: "bastion.example.com".to_owned(),
+                    port: None,
+                    user_name: Some("jump".to_owned()),
+                    identify_file: Some("/tmp/jump_id".into()),
+                },
+                SshJumpHost {
+                    host: "inner-bastion".to_owned(),
+                    port: Some(2222),
+                    user_name: None,
+                    identify_file: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ssh_tunnel_with_host_alias() {
+        let data = r"
+            control_path_directory: /tmp/tunka
+            tunnels:
+                - type: ssh
+                  name: ssh-tunnel
+                  listen_host: 127.0.0.1
+                  listen_port: 8051
+                  remote_host: prod
+                  ssh_config: $HOME/.ssh/work_config
+            ";
+        let config = Config::from_str(data).unwrap();
+        assert_eq!(
+            config.tunnels.first(),
+            Some(&Tunnel::Ssh {
+                name: "ssh-tunnel".to_owned(),
+                description: None,
+                listen_host: "127.0.0.1".to_owned(),
+                listen_port: 8051,
+                remote_host: "prod".to_owned(),
+                remote_port: None,
+                user_name: None,
+                identify_file: None,
+                identity_passphrase: None,
+                ssh_config: Some("$HOME/.ssh/work_config".into()),
+                local_forwards: Vec::new(),
+                remote_forwards: Vec::new(),
+                jump_hosts: Vec::new(),
+                ssh_options: BTreeMap::new(),
+                extra_args: Vec::new(),
             })
         );
     }
+
+    #[test]
+    fn test_ssh_tunnel_with_ssh_options() {
+        let data = r"
+            control_path_directory: /tmp/tunka
+            tunnels:
+                - type: ssh
+                  name: ssh-tunnel
+                  listen_host: 127.0.0.1
+                  listen_port: 8051
+                  remote_host: prod
+                  ssh_options:
+                    ServerAliveInterval: 30
+                    StrictHostKeyChecking: accept-new
+                    Compression: true
+                  extra_args: [-C]
+            ";
+        let config = Config::from_str(data).unwrap();
+        let Some(Tunnel::Ssh { ssh_options, extra_args, .. }) = config.tunnels.first() else {
+            panic!("expected ssh tunnel");
+        };
+        assert_eq!(
+            ssh_options,
+            &BTreeMap::from([
+                ("Compression".to_owned(), "yes".to_owned()),
+                ("ServerAliveInterval".to_owned(), "30".to_owned()),
+                ("StrictHostKeyChecking".to_owned(), "accept-new".to_owned()),
+            ])
+        );
+        assert_eq!(extra_args, &["-C"]);
+    }
+
+    #[test]
+    fn test_container_runtime() {
+        let data = r"
+            control_path_directory: /tmp/tunka
+            container_runtime: podman
+            container_backend: api
+            tunnels:
+                - type: docker
+                  name: docker-tunnel
+                  image_name: docker-tunnel
+                  container_name: docker-tunnel
+                  container_port: 8118
+                  listen_host: 127.0.0.1
+                  listen_port: 3128
+                - type: docker-openvpn
+                  name: docker-openvpn-tunnel
+                  container_runtime: nerdctl
+                  image_name: docker-openvpn
+                  container_name: docker-openvpn
+                  container_port: 8118
+                  listen_host: 127.0.0.1
+                  listen_port: 3129
+                  config_file: /tmp/config.ovpn
+            ";
+        let config = Config::from_str(data).unwrap();
+        assert_eq!(config.container_runtime, ContainerRuntime::Podman);
+        assert_eq!(config.container_backend, ContainerBackend::Api);
+        assert!(matches!(
+            config.tunnels.get(1),
+            Some(Tunnel::DockerOpenVPN { container_runtime: Some(ContainerRuntime::Nerdctl), .. })
+        ));
+    }
+
+    #[test]
+    fn test_docker_openvpn_tunnel_with_options() {
+        let data = r"
+            control_path_directory: /tmp/tunka
+            tunnels:
+                - type: docker-openvpn
+                  name: docker-openvpn-tunnel
+                  image_name: docker-openvpn
+                  container_name: docker-openvpn
+                  container_port: 8118
+                  listen_host: 127.0.0.1
+                  listen_port: 3129
+                  config_file: $HOME/vpn/client.ovpn
+                  config_target: /vpn/client.conf
+                  extra_files:
+                    - host_endpoint: $HOME/vpn/ca.crt
+                      container_endpoint: /vpn/ca.crt
+                  openvpn_args: [--verb, '4']
+                  dns: [1.1.1.1]
+            ";
+        let config = Config::from_str(data).unwrap();
+        let Some(Tunnel::DockerOpenVPN { openvpn, options, .. }) = config.tunnels.first() else {
+            panic!("expected a docker-openvpn tunnel");
+        };
+        assert_eq!(openvpn.config_target, PathBuf::from("/vpn/client.conf"));
+        assert_eq!(openvpn.auth_target, PathBuf::from("/auth.txt"));
+        assert_eq!(openvpn.extra_files.len(), 1);
+        assert_eq!(openvpn.openvpn_args, ["--verb", "4"]);
+        assert_eq!(options.dns, ["1.1.1.1"]);
+    }
+
+    #[test]
+    fn test_docker_wireguard_tunnel() {
+        let data = r"
+            control_path_directory: /tmp/tunka
+            tunnels:
+                - type: docker-wireguard
+                  name: docker-wireguard-tunnel
+                  image_name: wireguard-socks
+                  container_name: docker-wireguard
+                  container_port: 1080
+                  listen_host: 127.0.0.1
+                  listen_port: 1081
+                  config_file: $HOME/vpn/wg-work.conf
+                  sysctls:
+                    net.ipv6.conf.all.disable_ipv6: '0'
+            ";
+        let config = Config::from_str(data).unwrap();
+        let Some(Tunnel::DockerWireGuard { config_file, wireguard, options, .. }) =
+            config.tunnels.first()
+        else {
+            panic!("expected a docker-wireguard tunnel");
+        };
+        assert_eq!(config_file, &PathBuf::from("$HOME/vpn/wg-work.conf"));
+        assert_eq!(wireguard.config_target, PathBuf::from("/etc/wireguard/wg0.conf"));
+        assert_eq!(options.sysctls["net.ipv6.conf.all.disable_ipv6"], "0");
+    }
+
+    #[test]
+    fn test_docker_openconnect_tunnel() {
+        let data = r"
+            control_path_directory: /tmp/tunka
+            tunnels:
+                - type: docker-openconnect
+                  name: docker-openconnect-tunnel
+                  image_name: openconnect-proxy
+                  container_name: docker-openconnect
+                  container_port: 8888
+                  listen_host: 127.0.0.1
+                  listen_port: 8888
+                  server: https://vpn.example.com
+                  protocol: globalprotect
+                  user_name: the-user
+                  password:
+                    command: pass show vpn/work
+                  otp:
+                    env: VPN_OTP
+                  server_cert: pin-sha256:AAAA
+            ";
+        let config = Config::from_str(data).unwrap();
+        let Some(Tunnel::DockerOpenConnect { server, openconnect, .. }) = config.tunnels.first()
+        else {
+            panic!("expected a docker-openconnect tunnel");
+        };
+        assert_eq!(server, "https://vpn.example.com");
+        assert_eq!(openconnect.protocol.as_str(), "gp");
+        assert_eq!(openconnect.otp, Some(SecretSource::Env("VPN_OTP".to_owned())));
+        assert_eq!(openconnect.server_cert.as_deref(), Some("pin-sha256:AAAA"));
+    }
+
+    #[test]
+    fn test_tor_tunnel() {
+        let data = r"
+            control_path_directory: /tmp/tunka
+            tunnels:
+                - type: tor
+                  name: tor-tunnel
+                  listen_host: 127.0.0.1
+                  listen_port: 9150
+                  exit_countries: [de, ch]
+                  tor_options:
+                    NumEntryGuards: 3
+                    SafeSocks: true
+                - type: tor
+                  name: tor-container
+                  listen_host: 127.0.0.1
+                  listen_port: 9151
+                  bootstrap_timeout: 300
+                  container:
+                    image_name: tor-proxy
+                    container_name: tunka-tor
+            ";
+        let config = Config::from_str(data).unwrap();
+        let Some(Tunnel::Tor { tor_options, container: None, .. }) = config.tunnels.first() else {
+            panic!("expected a native tor tunnel");
+        };
+        assert_eq!(tor_options.get("NumEntryGuards").map(String::as_str), Some("3"));
+        assert_eq!(tor_options.get("SafeSocks").map(String::as_str), Some("1"));
+        assert!(matches!(
+            config.tunnels.get(1),
+            Some(Tunnel::Tor { bootstrap_timeout: Some(300), container: Some(container), .. })
+                if container.image_name == "tor-proxy"
+        ));
+    }
+
+    #[test]
+    fn test_kubernetes_tunnel() {
+        let data = r"
+            control_path_directory: /tmp/tunka
+            tunnels:
+                - type: kubernetes
+                  name: grafana
+                  context: prod
+                  namespace: monitoring
+                  resource: svc/grafana
+                  listen_host: 127.0.0.1
+                  listen_port: 3000
+                  remote_port: 80
+            ";
+        let config = Config::from_str(data).unwrap();
+        assert!(matches!(
+            config.tunnels.first(),
+            Some(Tunnel::Kubernetes { context: Some(context), kubectl: None, resource, remote_port: 80, .. })
+                if context == "prod" && resource == "svc/grafana"
+        ));
+    }
+
+    #[test]
+    fn test_command_tunnel() {
+        let data = r"
+            control_path_directory: /tmp/tunka
+            tunnels:
+                - type: command
+                  name: iap
+                  command: [gcloud, compute, start-iap-tunnel, db-1, '5432', --local-host-port=localhost:5432]
+                  environment:
+                    CLOUDSDK_CORE_PROJECT: the-project
+                  readiness:
+                    tcp: localhost:5432
+                - type: command
+                  name: sshuttle
+                  command: [sshuttle, -r, bastion, 10.0.0.0/8]
+                  readiness:
+                    output: 'Connected\.'
+                  ready_timeout: 60
+            ";
+        let config = Config::from_str(data).unwrap();
+        assert!(matches!(
+            config.tunnels.first(),
+            Some(Tunnel::Command { readiness: Some(ReadinessProbe::Tcp(address)), command, .. })
+                if address == "localhost:5432" && command.len() == 6
+        ));
+        assert!(matches!(
+            config.tunnels.get(1),
+            Some(Tunnel::Command {
+                readiness: Some(ReadinessProbe::Output(pattern)),
+                ready_timeout: Some(60),
+                ..
+            }) if pattern == r"Connected\."
+        ));
+    }
+
+    #[test]
+    fn test_relay_tunnel() {
+        let data = r"
+            control_path_directory: /tmp/tunka
+            tunnels:
+                - type: relay
+                  name: postgres
+                  listen_host: 0.0.0.0
+                  listen_port: 15432
+                  target_host: 10.0.0.5
+                  target_port: 5432
+                  max_connections: 8
+            ";
+        let config = Config::from_str(data).unwrap();
+        assert!(matches!(
+            config.tunnels.first(),
+            Some(Tunnel::Relay { target_port: 5432, max_connections: Some(8), .. })
+        ));
+    }
+
+    #[test]
+    fn test_http_proxy_tunnel() {
+        let data = r"
+            control_path_directory: /tmp/tunka
+            tunnels:
+                - type: http-proxy
+                  name: web
+                  listen_host: 127.0.0.1
+                  listen_port: 8118
+                  upstream: bastion
+                - type: ssh
+                  name: bastion
+                  remote_host: bastion
+                  listen_host: 0.0.0.0
+                  listen_port: 1080
+            ";
+        let manager = Config::from_str(data).unwrap().into_manager().unwrap();
+        assert_eq!(manager.tunnels["web"].upstream(), Some("bastion"));
+        assert_eq!(manager.tunnels["bastion"].socks_endpoint(), Some(("0.0.0.0", 1080)));
+
+        let data = r"
+            control_path_directory: /tmp/tunka
+            tunnels:
+                - type: http-proxy
+                  name: web
+                  listen_host: 127.0.0.1
+                  listen_port: 8118
+                  upstream: database
+                - type: relay
+                  name: database
+                  listen_host: 127.0.0.1
+                  listen_port: 15432
+                  target_host: 10.0.0.5
+                  target_port: 5432
+            ";
+        assert!(matches!(
+            Config::from_str(data).unwrap().into_manager(),
+            Err(Error::NoSocksEndpoint { .. })
+        ));
+    }
+
+    #[test]
+    fn test_secret_sources() {
+        let data = r"
+            control_path_directory: /tmp/tunka
+            tunnels:
+                - type: ssh
+                  name: ssh-tunnel
+                  remote_host: bastion
+                  identify_file: $HOME/.ssh/id_ed25519
+                  identity_passphrase:
+                    secret_service:
+                      service: ssh
+                      key: id_ed25519
+                  listen_host: 127.0.0.1
+                  listen_port: 1080
+                - type: docker-openvpn
+                  name: docker-openvpn-tunnel
+                  image_name: docker-openvpn
+                  container_name: docker-openvpn
+                  container_port: 8118
+                  listen_host: 127.0.0.1
+                  listen_port: 3129
+                  config_file: $HOME/vpn/client.ovpn
+                  auth:
+                    command: pass show vpn/work
+            ";
+        let config = Config::from_str(data).unwrap();
+        assert!(matches!(
+            config.tunnels.first(),
+            Some(Tunnel::Ssh { identity_passphrase: Some(SecretSource::SecretService(attributes)), .. })
+                if attributes.len() == 2
+        ));
+        assert!(matches!(
+            config.tunnels.get(1),
+            Some(Tunnel::DockerOpenVPN { auth: Some(SecretSource::Command(command)), .. })
+                if command == "pass show vpn/work"
+        ));
+
+        let data = r"
+            control_path_directory: /tmp/tunka
+            tunnels:
+                - type: docker-openvpn
+                  name: vpn
+                  image_name: docker-openvpn
+                  container_name: docker-openvpn
+                  container_port: 8118
+                  listen_host: 127.0.0.1
+                  listen_port: 3129
+                  config_file: $HOME/vpn/client.ovpn
+                  auth_file: $HOME/vpn/auth.txt
+                  auth:
+                    env: VPN_AUTH
+            ";
+        assert!(matches!(
+            Config::from_str(data).unwrap().into_manager(),
+            Err(Error::ConflictingOptions { tunnel, .. }) if tunnel == "vpn"
+        ));
+    }
+
+    #[test]
+    fn test_docker_tunnel_with_options() {
+        let data = r"
+            control_path_directory: /tmp/tunka
+            tunnels:
+                - type: docker
+                  name: docker-tunnel
+                  image_name: docker-tunnel
+                  container_name: docker-tunnel
+                  container_port: 8118
+                  listen_host: 127.0.0.1
+                  listen_port: 3128
+                  environment:
+                    LISTEN_PORT: 8118
+                    PASSWORD:
+                      file: $HOME/.config/proxy/password
+                  mounts:
+                    - host_endpoint: $HOME/.config/proxy/privoxy.conf
+                      container_endpoint: /etc/privoxy/config
+                  network: proxies
+                  pull: never
+                  keep_container: true
+                  build:
+                    context: $HOME/proxy
+                    build_args:
+                      VERSION: '3.0'
+                  ports:
+                    - listen_host: 127.0.0.1
+                      listen_port: 8119
+                      container_port: 8119
+                    - listen_host: 127.0.0.1
+                      listen_port: 5353
+                      container_port: 53
+                      protocol: udp
+                  dns: [1.1.1.1]
+                  cap_drop: [ALL]
+                  read_only: true
+                  user: nobody
+                  extra_run_args: [--memory=64m]
+            ";
+        let config = Config::from_str(data).unwrap();
+        let Some(Tunnel::Docker { options, .. }) = config.tunnels.first() else {
+            panic!("expected docker tunnel");
+        };
+        assert_eq!(options.environment.keys().collect::<Vec<_>>(), ["LISTEN_PORT", "PASSWORD"]);
+        assert!(options.build.is_some());
+        assert!(options.keep_container);
+        assert_eq!(options.ports.len(), 2);
+        assert_eq!(options.mounts.len(), 1);
+        assert!(options.mounts.iter().all(|mount| mount.read_only));
+        assert_eq!(options.network.as_deref(), Some("proxies"));
+        assert_eq!(options.dns, ["1.1.1.1"]);
+        assert_eq!(options.cap_drop, ["ALL"]);
+        assert!(options.cap_add.is_empty() && options.devices.is_empty());
+        assert!(options.read_only);
+        assert_eq!(options.user.as_deref(), Some("nobody"));
+        assert_eq!(options.extra_run_args, ["--memory=64m"]);
+    }
 }
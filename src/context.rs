@@ -38,6 +38,15 @@ pub struct Context {
 }
 
 impl Context {
+    #[cfg(test)]
+    pub fn new<P: AsRef<Path>>(user_name: &str, home_dir: &str, control_path_directory: P) -> Self {
+        Self {
+            user_name: user_name.to_owned(),
+            home_dir: home_dir.to_owned(),
+            control_path_directory: control_path_directory.as_ref().to_owned(),
+        }
+    }
+
     pub fn apply(&self, s: &str) -> String {
         s.replace("$USER", &self.user_name).replace("$HOME", &self.home_dir)
     }
@@ -17,8 +17,29 @@ pub enum Error {
     #[snafu(display("Tunnel not found: {tunnel}"))]
     TunnelNotFound { tunnel: String },
 
-    #[snafu(display("External command error, exit code: {code}"))]
-    ExternalCommand { code: i32 },
+    #[snafu(display(
+        "External command `{program} {}` failed, exit code: {code}, error: {stderr}",
+        args.join(" ")
+    ))]
+    ExternalCommand { program: String, args: Vec<String>, code: i32, stderr: String },
+
+    #[snafu(display(
+        "External command `{program} {}` was terminated by signal {signal}, error: {stderr}",
+        args.join(" ")
+    ))]
+    ExternalCommandTerminated { program: String, args: Vec<String>, signal: i32, stderr: String },
+
+    #[snafu(display("File {} of tunnel {tunnel} does not exist", file_path.display()))]
+    FileNotFound { tunnel: String, file_path: PathBuf },
+
+    #[snafu(display("Tunnel {tunnel} does not support {operation}"))]
+    UnsupportedOperation { tunnel: String, operation: &'static str },
+
+    #[snafu(display("Image {image} not found and pull policy is never"))]
+    ImageNotFound { image: String },
+
+    #[snafu(display("Tunnel {tunnel} has no build section"))]
+    NoImageBuild { tunnel: String },
 
     #[snafu(display("User name not found"))]
     UserNameNotFound,
@@ -41,6 +62,122 @@ pub enum Error {
     #[snafu(display("Error occurred while waiting for SSH process, error: {source}"))]
     WaitForSshProcess { source: std::io::Error },
 
+    #[snafu(display("SSH command `{command}` failed, error: {stderr}"))]
+    SshCommand { command: String, stderr: String },
+
+    #[snafu(display("Could not create log file {}, error: {source}", file_path.display()))]
+    CreateLogFile { file_path: PathBuf, source: std::io::Error },
+
+    #[snafu(display("Could not read log file {}, error: {source}", file_path.display()))]
+    ReadLogFile { file_path: PathBuf, source: std::io::Error },
+
+    #[snafu(display("Could not run secret command {program}, error: {source}"))]
+    SpawnSecretCommand { program: String, source: std::io::Error },
+
+    #[snafu(display("Environment variable {name} holding a secret is not set"))]
+    SecretEnvironmentVariableNotFound { name: String },
+
+    #[snafu(display("Could not write secret file {}, error: {source}", file_path.display()))]
+    WriteSecretFile { file_path: PathBuf, source: std::io::Error },
+
+    #[snafu(display("Could not create data directory {}, error: {source}", dir_path.display()))]
+    CreateDataDirectory { dir_path: PathBuf, source: std::io::Error },
+
+    #[snafu(display("Could not write configuration file {}, error: {source}", file_path.display()))]
+    WriteConfigFile { file_path: PathBuf, source: std::io::Error },
+
+    #[snafu(display("Could not read tor control cookie {}, error: {source}", file_path.display()))]
+    ReadTorCookie { file_path: PathBuf, source: std::io::Error },
+
+    #[snafu(display("Error occurred while talking to tor control socket, error: {source}"))]
+    TorControlIo { source: std::io::Error },
+
+    #[snafu(display("Tor control command failed: {message}"))]
+    TorControl { message: String },
+
+    #[snafu(display(
+        "Tor of tunnel {tunnel} did not finish bootstrap in time, progress: {progress}%"
+    ))]
+    TorBootstrapTimeout { tunnel: String, progress: u8 },
+
+    #[snafu(display("Could not run {program}, error: {source}"))]
+    SpawnProcess { program: String, source: std::io::Error },
+
+    #[snafu(display("Error occurred while waiting for process, error: {source}"))]
+    WaitForProcess { source: std::io::Error },
+
+    #[snafu(display("Could not write pid file {}, error: {source}", file_path.display()))]
+    WritePidFile { file_path: PathBuf, source: std::io::Error },
+
+    #[snafu(display("Invalid readiness pattern {pattern}, error: {source}"))]
+    InvalidReadinessPattern { pattern: String, source: regex::Error },
+
+    #[snafu(display("Tunnel {tunnel} has an empty command"))]
+    EmptyCommand { tunnel: String },
+
+    #[snafu(display("Could not locate the tunka executable, error: {source}"))]
+    CurrentExecutable { source: std::io::Error },
+
+    #[snafu(display("Could not listen on {address}, error: {source}"))]
+    BindSocket { address: String, source: std::io::Error },
+
+    #[snafu(display("Error occurred while talking to relay, error: {source}"))]
+    RelayIo { source: std::io::Error },
+
+    #[snafu(display(
+        "Image {image} of tunnel {tunnel} runs openconnect directly, which gets no password on \
+         stdin in a detached container, its entrypoint has to feed {} to openconnect",
+        password_target.display()
+    ))]
+    OpenConnectEntrypoint { tunnel: String, image: String, password_target: PathBuf },
+
+    #[snafu(display(
+        "Image {image} of tunnel {tunnel} has no entrypoint, the arguments of openconnect would \
+         be run as its command"
+    ))]
+    OpenConnectNoEntrypoint { tunnel: String, image: String },
+
+    #[snafu(display(
+        "Value of environment variable {name} spans several lines, which the container CLI can \
+         not pass"
+    ))]
+    MultilineEnvironmentValue { name: String },
+
+    #[snafu(display("Tunnel {tunnel} sets both {option} and {other_option}"))]
+    ConflictingOptions { tunnel: String, option: String, other_option: String },
+
+    #[snafu(display("Upstream {upstream} of tunnel {tunnel} does not provide a SOCKS endpoint"))]
+    NoSocksEndpoint { tunnel: String, upstream: String },
+
+    #[snafu(display("Process of tunnel {tunnel} did not become ready"))]
+    ProcessNotReady { tunnel: String },
+
+    #[snafu(display("Could not start tunnel {tunnel}, error: {source}"))]
+    StartTunnel {
+        tunnel: String,
+        // `Self` would refer to the context selector inside the generated code
+        #[allow(clippy::use_self)]
+        #[snafu(source(from(Error, Box::new)))]
+        source: Box<Error>,
+    },
+
+    #[snafu(display("Could not read environment file {}, error: {source}", file_path.display()))]
+    ReadEnvironmentFile { file_path: PathBuf, source: std::io::Error },
+
+    #[snafu(display("Could not connect to Docker Engine at {}, error: {source}", socket_path.display()))]
+    ConnectDockerEngine { socket_path: PathBuf, source: std::io::Error },
+
+    #[snafu(display("Error occurred while talking to Docker Engine, error: {source}"))]
+    DockerEngineIo { source: std::io::Error },
+
+    #[snafu(display(
+        "Docker Engine API request {method} {path} failed, status: {status}, error: {message}"
+    ))]
+    DockerEngineResponse { method: String, path: String, status: u16, message: String },
+
+    #[snafu(display("Invalid response from Docker Engine: {message}"))]
+    InvalidDockerEngineResponse { message: String },
+
     #[snafu(display("Error occurred while spawning Docker command, error: {source}"))]
     SpawnDockerCommand { source: std::io::Error },
 
@@ -0,0 +1,7 @@
+/// 64-bit FNV-1a, unlike the hasher of the standard library its values stay
+/// the same across builds.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
@@ -2,6 +2,7 @@ mod command;
 mod config;
 mod context;
 mod error;
+mod hash;
 mod tunnel;
 
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
@@ -0,0 +1,213 @@
+use std::{
+    collections::BTreeMap,
+    net::{TcpStream, ToSocketAddrs},
+    path::PathBuf,
+    process::Command,
+    time::Duration,
+};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+
+use crate::{
+    context::Context,
+    error::{self, Error},
+    tunnel::{
+        docker::EnvironmentValue, log_file, process::BackgroundProcess, Tunnel, TunnelMeta,
+        TunnelType,
+    },
+};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Tells when a started command is ready to be used.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadinessProbe {
+    /// Waits until a TCP connection to `host:port` succeeds.
+    Tcp(String),
+    /// Waits until the output of the command matches a regular expression.
+    Output(String),
+}
+
+impl ReadinessProbe {
+    fn is_ready(&self, output: &str, pattern: Option<&Regex>) -> Result<bool, Error> {
+        match self {
+            Self::Tcp(address) => {
+                let addrs = address
+                    .to_socket_addrs()
+                    .with_context(|_| error::ResolveSocketAddrSnafu { address: address.clone() })?;
+                Ok(addrs
+                    .into_iter()
+                    .any(|addr| TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT).is_ok()))
+            }
+            Self::Output(_) => Ok(pattern.is_some_and(|pattern| pattern.is_match(output))),
+        }
+    }
+
+    fn pattern(&self) -> Result<Option<Regex>, Error> {
+        match self {
+            Self::Tcp(_) => Ok(None),
+            Self::Output(pattern) => Regex::new(pattern)
+                .map(Some)
+                .with_context(|_| error::InvalidReadinessPatternSnafu { pattern: pattern.clone() }),
+        }
+    }
+}
+
+/// Runs an arbitrary command in the background, for tools like `sshuttle` or
+/// `cloudflared access tcp` which tunka does not model itself.
+#[derive(Clone, Debug)]
+pub struct CommandTunnel {
+    pub meta: TunnelMeta,
+    /// Program followed by its arguments, no shell is involved.
+    pub command: Vec<String>,
+    pub environment: BTreeMap<String, EnvironmentValue>,
+    pub working_directory: Option<PathBuf>,
+    pub readiness: Option<ReadinessProbe>,
+    pub ready_timeout: Duration,
+}
+
+impl CommandTunnel {
+    pub const DEFAULT_READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+    #[inline]
+    pub fn pid_file(&self, context: &Context) -> PathBuf {
+        context.control_path_directory().join(format!("{}.pid", self.name()))
+    }
+
+    #[inline]
+    pub fn log_file(&self, context: &Context) -> PathBuf {
+        context.control_path_directory().join(format!("{}.log", self.name()))
+    }
+
+    fn process(&self, context: &Context) -> Result<BackgroundProcess, Error> {
+        let Some((program, args)) = self.command.split_first() else {
+            return Err(Error::EmptyCommand { tunnel: self.name().to_owned() });
+        };
+        Ok(BackgroundProcess {
+            program: context.apply(program),
+            args: args.iter().map(|arg| context.apply(arg)).collect(),
+            pid_file: self.pid_file(context),
+            log_file: self.log_file(context),
+        })
+    }
+}
+
+impl Tunnel for CommandTunnel {
+    #[inline]
+    fn meta(&self) -> &TunnelMeta { &self.meta }
+
+    #[inline]
+    fn tunnel_type(&self) -> TunnelType { TunnelType::Command }
+
+    fn start(&self, context: &Context) -> Result<(), Error> {
+        let process = self.process(context)?;
+        if process.is_running() {
+            return Ok(());
+        }
+
+        let mut command = Command::new(&process.program);
+        for (key, value) in &self.environment {
+            let _unused = command.env(key, value.resolve(context)?);
+        }
+        if let Some(working_directory) = &self.working_directory {
+            let _unused = command.current_dir(context.apply_path(working_directory));
+        }
+        let mut child = process.spawn(&mut command)?;
+
+        let Some(readiness) = &self.readiness else {
+            return Ok(());
+        };
+        let pattern = readiness.pattern()?;
+        process.wait_until_ready(self.name(), &mut child, self.ready_timeout, |output| {
+            readiness.is_ready(output, pattern.as_ref()).unwrap_or_default()
+        })
+    }
+
+    #[inline]
+    fn stop(&self, context: &Context) -> Result<(), Error> { self.process(context)?.terminate() }
+
+    #[inline]
+    fn is_running(&self, context: &Context) -> Result<bool, Error> {
+        Ok(self.process(context)?.is_running())
+    }
+
+    #[inline]
+    fn logs(&self, context: &Context, follow: bool, tail: Option<usize>) -> Result<(), Error> {
+        log_file::print(&self.log_file(context), follow, tail)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    fn context() -> Context {
+        let dir = std::env::temp_dir().join(format!("tunka-command-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Context::new("the-user", "/home/the-user", dir)
+    }
+
+    fn tunnel(name: &str, script: &str, readiness: Option<ReadinessProbe>) -> CommandTunnel {
+        CommandTunnel {
+            meta: TunnelMeta { name: name.to_owned(), description: None },
+            command: vec!["sh".to_owned(), "-c".to_owned(), script.to_owned(), name.to_owned()],
+            environment: BTreeMap::from([(
+                "TUNKA_GREETING".to_owned(),
+                EnvironmentValue::Value("hello $USER".to_owned()),
+            )]),
+            working_directory: Some(std::env::temp_dir()),
+            readiness,
+            ready_timeout: Duration::from_secs(5),
+        }
+    }
+
+    #[test]
+    fn test_output_readiness() {
+        let context = context();
+        let tunnel = tunnel(
+            "command-output",
+            "echo \"$TUNKA_GREETING from $PWD\"; echo 'listening on port 8080'; while :; do sleep \
+             1; done",
+            Some(ReadinessProbe::Output(r"listening on port \d+".to_owned())),
+        );
+        tunnel.start(&context).unwrap();
+        assert!(tunnel.is_running(&context).unwrap());
+        let log = std::fs::read_to_string(tunnel.log_file(&context)).unwrap();
+        assert!(log.starts_with(&format!("hello the-user from {}", std::env::temp_dir().display())));
+
+        tunnel.stop(&context).unwrap();
+        assert!(!tunnel.pid_file(&context).exists());
+    }
+
+    #[test]
+    fn test_tcp_readiness() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let probe = ReadinessProbe::Tcp(address);
+        assert!(probe.is_ready("", None).unwrap());
+
+        drop(listener);
+        assert!(!probe.is_ready("", None).unwrap());
+    }
+
+    #[test]
+    fn test_exit_before_ready() {
+        let context = context();
+        let tunnel = tunnel(
+            "command-exit",
+            "echo 'no such host' >&2; exit 2",
+            Some(ReadinessProbe::Output("ready".to_owned())),
+        );
+        let err = tunnel.start(&context).unwrap_err();
+        assert!(matches!(&err, Error::ExternalCommand { code: 2, .. }), "{err}");
+        assert!(!tunnel.is_running(&context).unwrap());
+
+        let tunnel = CommandTunnel { command: Vec::new(), ..tunnel };
+        assert!(matches!(tunnel.start(&context), Err(Error::EmptyCommand { .. })));
+    }
+}
@@ -1,25 +1,491 @@
 use std::{
-    net::ToSocketAddrs,
-    path::PathBuf,
-    process::{Command, ExitStatus, Stdio},
+    collections::BTreeMap,
+    net::{SocketAddr, ToSocketAddrs},
+    os::unix::process::ExitStatusExt,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
 };
 
+use serde::{de, Deserialize, Deserializer, Serialize};
+use serde_json::{json, Value};
 use snafu::ResultExt;
 
 use crate::{
     context::Context,
     error::{self, Error},
-    tunnel::{Tunnel, TunnelMeta, TunnelType},
+    tunnel::{docker_engine::DockerEngine, secret::SecretFile, Tunnel, TunnelMeta, TunnelType},
 };
 
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub struct DockerMount {
     pub host_endpoint: PathBuf,
     pub container_endpoint: PathBuf,
+    #[serde(default = "default_read_only")]
+    pub read_only: bool,
+}
+
+const fn default_read_only() -> bool { true }
+
+/// Value of an environment variable, either given inline or read from a file
+/// when the container starts.
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum EnvironmentValue {
+    Value(String),
+    File { file: PathBuf },
+}
+
+impl<'de> Deserialize<'de> for EnvironmentValue {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match serde_yaml::Value::deserialize(deserializer)? {
+            serde_yaml::Value::String(value) => Ok(Self::Value(value)),
+            serde_yaml::Value::Number(value) => Ok(Self::Value(value.to_string())),
+            serde_yaml::Value::Bool(value) => Ok(Self::Value(value.to_string())),
+            serde_yaml::Value::Mapping(mapping) => {
+                #[derive(Deserialize)]
+                struct FileValue {
+                    file: PathBuf,
+                }
+                let FileValue { file } =
+                    serde_yaml::from_value(serde_yaml::Value::Mapping(mapping))
+                        .map_err(de::Error::custom)?;
+                Ok(Self::File { file })
+            }
+            _ => Err(de::Error::custom(
+                "environment value must be a string, number, boolean or `file: <path>`",
+            )),
+        }
+    }
+}
+
+impl EnvironmentValue {
+    pub fn resolve(&self, context: &Context) -> Result<String, Error> {
+        match self {
+            Self::Value(value) => Ok(context.apply(value)),
+            Self::File { file } => {
+                let file_path = context.apply_path(file);
+                let value = std::fs::read_to_string(&file_path)
+                    .with_context(|_| error::ReadEnvironmentFileSnafu { file_path })?;
+                Ok(value.trim_end_matches(['\r', '\n']).to_owned())
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PortProtocol {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+impl PortProtocol {
+    #[inline]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Tcp => "tcp",
+            Self::Udp => "udp",
+        }
+    }
+}
+
+/// A port published in addition to the main `listen_host:listen_port`
+/// mapping of a tunnel.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct PortMapping {
+    pub listen_host: String,
+    pub listen_port: u16,
+    pub container_port: u16,
+    #[serde(default)]
+    pub protocol: PortProtocol,
+}
+
+impl PortMapping {
+    fn resolve(&self) -> Result<PortBinding, Error> {
+        let address = format!("{}:{}", self.listen_host, self.listen_port);
+        let host_addr = address
+            .to_socket_addrs()
+            .with_context(|_| error::ResolveSocketAddrSnafu { address })?
+            .next()
+            .ok_or_else(|| Error::DomainNotFound { domain: self.listen_host.clone() })?;
+        Ok(PortBinding { host_addr, container_port: self.container_port, protocol: self.protocol })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PullPolicy {
+    Always,
+    #[default]
+    Missing,
+    Never,
+}
+
+/// Builds the image of a tunnel instead of pulling it.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct ImageBuild {
+    pub context: PathBuf,
+    #[serde(default)]
+    pub dockerfile: Option<PathBuf>,
+    #[serde(default)]
+    pub build_args: BTreeMap<String, String>,
+}
+
+impl ImageBuild {
+    pub fn to_build_args(&self, context: &Context, image: &str) -> Vec<String> {
+        let mut args = vec!["build".to_owned(), "--tag".to_owned(), image.to_owned()];
+        if let Some(dockerfile) = &self.dockerfile {
+            args.push("--file".to_owned());
+            args.push(context.apply_path(dockerfile).to_string_lossy().into_owned());
+        }
+        for (key, value) in &self.build_args {
+            args.push("--build-arg".to_owned());
+            args.push(format!("{key}={}", context.apply(value)));
+        }
+        args.push(context.apply_path(&self.context).to_string_lossy().into_owned());
+        args
+    }
+}
+
+/// Options shared by all container based tunnels.
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct ContainerOptions {
+    #[serde(default)]
+    pub pull: PullPolicy,
+    #[serde(default)]
+    pub build: Option<ImageBuild>,
+    #[serde(default)]
+    pub ports: Vec<PortMapping>,
+    #[serde(default)]
+    pub environment: BTreeMap<String, EnvironmentValue>,
+    #[serde(default)]
+    pub mounts: Vec<DockerMount>,
+    #[serde(default)]
+    pub network: Option<String>,
+    #[serde(default)]
+    pub dns: Vec<String>,
+    #[serde(default)]
+    pub cap_add: Vec<String>,
+    #[serde(default)]
+    pub cap_drop: Vec<String>,
+    #[serde(default)]
+    pub devices: Vec<String>,
+    #[serde(default)]
+    pub security_opt: Vec<String>,
+    /// Disables the security labelling of the container with
+    /// `label=disable`, which on enforcing hosts denies it `/dev/net/tun` and
+    /// bind mounts not labelled for containers. Off by default, since it
+    /// weakens the confinement of the container.
+    #[serde(default)]
+    pub disable_selinux_labels: bool,
+    #[serde(default)]
+    pub sysctls: BTreeMap<String, String>,
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default)]
+    pub extra_run_args: Vec<String>,
+    /// Keeps the container after it exits instead of running it with `--rm`,
+    /// so its logs can still be inspected.
+    #[serde(default)]
+    pub keep_container: bool,
+}
+
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ContainerRuntime {
+    #[default]
+    Docker,
+    Podman,
+    Nerdctl,
+}
+
+impl ContainerRuntime {
+    #[inline]
+    pub const fn program(self) -> &'static str {
+        match self {
+            Self::Docker => "docker",
+            Self::Podman => "podman",
+            Self::Nerdctl => "nerdctl",
+        }
+    }
+
+    /// nerdctl refuses to combine `--rm` with `--detach`, leftover containers
+    /// are removed by `stop` or the next `start` instead.
+    #[inline]
+    pub const fn supports_detached_rm(self) -> bool { !matches!(self, Self::Nerdctl) }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContainerBackend {
+    /// Runs the command line interface of the container runtime.
+    #[default]
+    Cli,
+    /// Talks to the Docker Engine API, falling back to the command line
+    /// interface if the engine is not reachable. Only applies to the docker
+    /// runtime.
+    Api,
+}
+
+/// Container settings shared by all tunnels unless a tunnel overrides them.
+#[derive(Clone, Debug, Default)]
+pub struct ContainerDefaults {
+    pub runtime: ContainerRuntime,
+    pub engine: Option<DockerEngine>,
+    /// Identifies the configuration file the containers are started from.
+    pub config_id: String,
+}
+
+/// Marks containers started by tunka.
+pub const LABEL_OWNER: &str = "tunka";
+pub const LABEL_TUNNEL: &str = "tunka.tunnel";
+pub const LABEL_CONFIG: &str = "tunka.config";
+
+/// A container carrying the labels of tunka, as found on the host.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LabelledContainer {
+    pub name: String,
+    pub tunnel: String,
+    pub config_id: String,
+    pub status: String,
+}
+
+impl LabelledContainer {
+    /// Whether the container was started from the configuration `config_id`
+    /// by a tunnel that is not part of it anymore.
+    pub fn is_orphan(&self, config_id: &str, tunnels: &[&DockerTunnel]) -> bool {
+        self.config_id == config_id
+            && !tunnels
+                .iter()
+                .any(|tunnel| tunnel.name() == self.tunnel && tunnel.container_name == self.name)
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PortBinding {
+    pub host_addr: SocketAddr,
+    pub container_port: u16,
+    pub protocol: PortProtocol,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BindMount {
+    pub source: String,
+    pub target: String,
+    pub read_only: bool,
+}
+
+/// Everything needed to run a container, independent of how it is run.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ContainerSpec {
+    pub name: String,
+    pub image: String,
+    pub labels: BTreeMap<String, String>,
+    pub auto_remove: bool,
+    pub ports: Vec<PortBinding>,
+    pub mounts: Vec<BindMount>,
+    pub devices: Vec<String>,
+    pub cap_add: Vec<String>,
+    pub cap_drop: Vec<String>,
+    pub security_opt: Vec<String>,
+    pub sysctls: BTreeMap<String, String>,
+    pub read_only: bool,
+    pub user: Option<String>,
+    pub environment: Vec<(String, String)>,
+    pub network: Option<String>,
+    pub dns: Vec<String>,
+    /// Overrides the command of the image if not empty.
+    pub command: Vec<String>,
+    /// Arguments passed to `run` as is, only supported by the CLI backend.
+    pub extra_args: Vec<String>,
+}
+
+impl ContainerSpec {
+    /// Grants the container access to `/dev/net/tun`, as needed by VPN clients.
+    pub fn request_tun(&mut self) {
+        fn add(values: &mut Vec<String>, value: &str) {
+            if !values.iter().any(|existing| existing == value) {
+                values.push(value.to_owned());
+            }
+        }
+
+        add(&mut self.devices, "/dev/net/tun");
+        add(&mut self.cap_add, "NET_ADMIN");
+    }
+
+    /// Passes arguments to the program of the image, either as the container
+    /// command or joined by spaces in the environment variable `env`.
+    pub fn pass_arguments(&mut self, args: &[String], env: Option<&String>) {
+        match env {
+            _ if args.is_empty() => {}
+            Some(name) => self.environment.push((name.clone(), args.join(" "))),
+            None => self.command = args.to_vec(),
+        }
+    }
+
+    /// Returns the arguments of `run`, the environment is read from
+    /// `env_file`, see [`ContainerSpec::env_file_contents`].
+    pub fn to_run_args(&self, env_file: Option<&Path>) -> Vec<String> {
+        let mut args = vec!["run".to_owned(), "--detach".to_owned()];
+        if self.auto_remove {
+            args.push("--rm".to_owned());
+        }
+        args.extend(["--name".to_owned(), self.name.clone()]);
+        for (key, value) in &self.labels {
+            args.push("--label".to_owned());
+            args.push(format!("{key}={value}"));
+        }
+        for port in &self.ports {
+            args.push("--publish".to_owned());
+            args.push(format!(
+                "{}:{}/{}",
+                port.host_addr,
+                port.container_port,
+                port.protocol.as_str()
+            ));
+        }
+        args.extend(self.devices.iter().map(|device| format!("--device={device}")));
+        args.extend(self.cap_add.iter().map(|cap| format!("--cap-add={cap}")));
+        args.extend(self.cap_drop.iter().map(|cap| format!("--cap-drop={cap}")));
+        args.extend(self.security_opt.iter().map(|opt| format!("--security-opt={opt}")));
+        args.extend(self.sysctls.iter().map(|(key, value)| format!("--sysctl={key}={value}")));
+        if self.read_only {
+            args.push("--read-only".to_owned());
+        }
+        if let Some(user) = &self.user {
+            args.push(format!("--user={user}"));
+        }
+        if let Some(network) = &self.network {
+            args.push(format!("--network={network}"));
+        }
+        args.extend(self.dns.iter().map(|dns| format!("--dns={dns}")));
+        if let Some(env_file) = env_file {
+            args.push("--env-file".to_owned());
+            args.push(env_file.to_string_lossy().into_owned());
+        }
+        for mount in &self.mounts {
+            args.push("--mount".to_owned());
+            args.push(format!(
+                "type=bind,source={},destination={},readonly={}",
+                mount.source, mount.target, mount.read_only
+            ));
+        }
+        args.extend(self.extra_args.iter().cloned());
+        args.push(self.image.clone());
+        args.extend(self.command.iter().cloned());
+        args
+    }
+
+    /// Returns the environment in the format of `--env-file`, which has no
+    /// way to continue a value on the next line.
+    pub fn env_file_contents(&self) -> Result<String, Error> {
+        self.environment
+            .iter()
+            .map(|(key, value)| {
+                if value.contains('\n') {
+                    return Err(Error::MultilineEnvironmentValue { name: key.clone() });
+                }
+                Ok(format!("{key}={value}\n"))
+            })
+            .collect()
+    }
+
+    /// Returns the body of a Docker Engine API `POST /containers/create`.
+    pub fn to_create_body(&self) -> Value {
+        let mut exposed_ports = serde_json::Map::new();
+        let mut port_bindings = serde_json::Map::new();
+        for port in &self.ports {
+            let key = format!("{}/{}", port.container_port, port.protocol.as_str());
+            let _unused = exposed_ports.insert(key.clone(), json!({}));
+            let bindings = port_bindings.entry(key).or_insert_with(|| json!([]));
+            if let Some(bindings) = bindings.as_array_mut() {
+                bindings.push(json!({
+                    "HostIp": port.host_addr.ip().to_string(),
+                    "HostPort": port.host_addr.port().to_string(),
+                }));
+            }
+        }
+
+        json!({
+            "Image": self.image,
+            "Labels": self.labels,
+            "Cmd": (!self.command.is_empty()).then_some(&self.command),
+            "User": self.user.clone().unwrap_or_default(),
+            "Env": self.environment.iter().map(|(key, value)| format!("{key}={value}")).collect::<Vec<_>>(),
+            "ExposedPorts": exposed_ports,
+            "HostConfig": {
+                "NetworkMode": self.network,
+                "Dns": self.dns,
+                "AutoRemove": self.auto_remove,
+                "PortBindings": port_bindings,
+                "Mounts": self.mounts.iter().map(|mount| json!({
+                    "Type": "bind",
+                    "Source": mount.source,
+                    "Target": mount.target,
+                    "ReadOnly": mount.read_only,
+                })).collect::<Vec<_>>(),
+                "Devices": self.devices.iter().map(|device| json!({
+                    "PathOnHost": device,
+                    "PathInContainer": device,
+                    "CgroupPermissions": "rwm",
+                })).collect::<Vec<_>>(),
+                "CapAdd": self.cap_add,
+                "CapDrop": self.cap_drop,
+                "SecurityOpt": self.security_opt,
+                "Sysctls": self.sysctls,
+                "ReadonlyRootfs": self.read_only,
+            },
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ContainerState {
+    Running,
+    Restarting,
+    Paused,
+    Exited,
+    Missing,
+}
+
+impl ContainerState {
+    /// Parses the `.State.Status` field reported by `docker inspect`.
+    pub fn from_status(status: &str) -> Self {
+        match status.trim() {
+            "running" => Self::Running,
+            "restarting" => Self::Restarting,
+            "paused" => Self::Paused,
+            _ => Self::Exited,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct DockerTunnel {
     pub meta: TunnelMeta,
+    pub runtime: ContainerRuntime,
+    pub engine: Option<DockerEngine>,
+    pub config_id: String,
+    pub options: ContainerOptions,
+    pub image_name: String,
+    pub container_name: String,
+    pub container_port: u16,
+    pub listen_host: String,
+    pub listen_port: u16,
+}
+
+/// The image and the ports of a configured container.
+#[derive(Clone, Debug)]
+pub struct ContainerTarget {
+    pub runtime: Option<ContainerRuntime>,
     pub image_name: String,
     pub container_name: String,
     pub container_port: u16,
@@ -28,62 +494,445 @@ pub struct DockerTunnel {
 }
 
 impl DockerTunnel {
-    pub fn start_with_mounts(
+    /// Builds the container of a configured tunnel, what the tunnel leaves
+    /// out is taken from `defaults`.
+    pub fn from_options(
+        meta: TunnelMeta,
+        target: ContainerTarget,
+        options: ContainerOptions,
+        defaults: &ContainerDefaults,
+    ) -> Self {
+        Self {
+            meta,
+            runtime: target.runtime.unwrap_or(defaults.runtime),
+            engine: defaults.engine.clone(),
+            config_id: defaults.config_id.clone(),
+            options,
+            image_name: target.image_name,
+            container_name: target.container_name,
+            container_port: target.container_port,
+            listen_host: target.listen_host,
+            listen_port: target.listen_port,
+        }
+    }
+
+    #[inline]
+    pub fn start_with_spec(&self, context: &Context, spec: &ContainerSpec) -> Result<(), Error> {
+        self.start_checked(context, spec, || Ok(()))
+    }
+
+    /// Starts the container like [`DockerTunnel::start_with_spec`], `check`
+    /// runs once the image is available and before the container does.
+    pub fn start_checked(
         &self,
         context: &Context,
-        mounts: &[DockerMount],
+        spec: &ContainerSpec,
+        check: impl FnOnce() -> Result<(), Error>,
     ) -> Result<(), Error> {
-        let listen_addr = {
-            let address = format!("{}:{}", self.listen_host, self.listen_port);
-            address
-                .to_socket_addrs()
-                .with_context(|_| error::ResolveSocketAddrSnafu { address })?
-                .next()
-                .ok_or(Error::DomainNotFound { domain: self.listen_host.clone() })?
+        match self.state()? {
+            ContainerState::Running => return Ok(()),
+            ContainerState::Missing => {}
+            // a leftover container would make `docker run --name` fail with a name conflict
+            ContainerState::Restarting | ContainerState::Paused | ContainerState::Exited => {
+                self.remove()?;
+            }
+        }
+
+        self.prepare_image(context)?;
+        check()?;
+
+        if spec.extra_args.is_empty() {
+            self.with_backend(
+                |engine| engine.run(spec).map(|_id| ()),
+                || self.run_container(context, spec),
+            )
+        } else {
+            self.run_container(context, spec)
+        }
+    }
+
+    fn run_container(&self, context: &Context, spec: &ContainerSpec) -> Result<(), Error> {
+        let (mut command, args, _env_file) = self.run_command(context, spec)?;
+        convert_output(self.runtime.program(), &args, &output(&mut command)?)
+    }
+
+    /// Returns the command running the container with its arguments, and the
+    /// env file to keep until it has run. The values of the environment are
+    /// neither arguments, which show up in `ps` and in error messages, nor in
+    /// the environment of the runtime, where e.g. `DOCKER_HOST` would redirect
+    /// it.
+    fn run_command(
+        &self,
+        context: &Context,
+        spec: &ContainerSpec,
+    ) -> Result<(Command, Vec<String>, Option<SecretFile>), Error> {
+        let env_file = if spec.environment.is_empty() {
+            None
+        } else {
+            let name = format!("{}.env", spec.name);
+            Some(SecretFile::create(context, &name, &spec.env_file_contents()?)?)
         };
+        let args = spec.to_run_args(env_file.as_ref().map(SecretFile::path));
+        let mut command = Command::new(self.runtime.program());
+        let _unused = command.args(&args);
+        Ok((command, args, env_file))
+    }
 
-        let mut args = vec![
-            "run".to_owned(),
-            "--detach".to_owned(),
-            "--rm".to_owned(),
-            "--name".to_owned(),
-            self.container_name.clone(),
-            "--publish".to_owned(),
-            format!("{listen_addr}:{}", self.container_port),
-            "--device=/dev/net/tun".to_owned(),
-            "--cap-add=NET_ADMIN".to_owned(),
-        ];
+    /// Makes sure the image is available according to the pull policy, or
+    /// builds it if the tunnel has a build section.
+    pub fn prepare_image(&self, context: &Context) -> Result<(), Error> {
+        match (&self.options.build, self.options.pull) {
+            (Some(_), PullPolicy::Always) => self.build_image(context),
+            (Some(_), _) => {
+                if self.image_exists()? {
+                    Ok(())
+                } else {
+                    self.build_image(context)
+                }
+            }
+            (None, PullPolicy::Always) => self.pull_image(),
+            (None, PullPolicy::Missing) => {
+                if self.image_exists()? {
+                    Ok(())
+                } else {
+                    self.pull_image()
+                }
+            }
+            (None, PullPolicy::Never) => {
+                if self.image_exists()? {
+                    Ok(())
+                } else {
+                    Err(Error::ImageNotFound { image: self.image_name.clone() })
+                }
+            }
+        }
+    }
 
+    pub fn image_exists(&self) -> Result<bool, Error> {
+        self.with_backend(
+            |engine| engine.image_exists(&self.image_name),
+            || {
+                let args = ["image".to_owned(), "inspect".to_owned(), self.image_name.clone()];
+                Ok(self.run(&args)?.status.success())
+            },
+        )
+    }
+
+    /// Returns the entrypoint of the image, empty if it has none.
+    pub fn image_entrypoint(&self) -> Result<Vec<String>, Error> {
+        self.with_backend(
+            |engine| engine.image_entrypoint(&self.image_name),
+            || {
+                let args = [
+                    "image".to_owned(),
+                    "inspect".to_owned(),
+                    "--format".to_owned(),
+                    "{{json .Config.Entrypoint}}".to_owned(),
+                    self.image_name.clone(),
+                ];
+                let output = self.run(&args)?;
+                convert_output(self.runtime.program(), &args, &output)?;
+                Ok(serde_json::from_slice(&output.stdout).ok().flatten().unwrap_or_default())
+            },
+        )
+    }
+
+    pub fn pull_image(&self) -> Result<(), Error> {
+        let cli = || self.run_checked(&["pull".to_owned(), self.image_name.clone()]);
+        self.with_backend(
+            |engine| match engine.pull(&self.image_name) {
+                // the API gets no credentials, the CLI reads them from its config and
+                // credential helpers
+                Err(Error::DockerEngineResponse { status: 401 | 403 | 404, message, .. }) => {
+                    tracing::info!(
+                        "Could not pull {} through Docker Engine, retry with {}, error: {message}",
+                        self.image_name,
+                        self.runtime.program()
+                    );
+                    cli()
+                }
+                result => result,
+            },
+            cli,
+        )
+    }
+
+    /// Builds the image with the CLI of the runtime, as the Docker Engine API
+    /// would need the build context uploaded as an archive.
+    pub fn build_image(&self, context: &Context) -> Result<(), Error> {
+        let build = self
+            .options
+            .build
+            .as_ref()
+            .ok_or_else(|| Error::NoImageBuild { tunnel: self.name().to_owned() })?;
+        self.run_checked(&build.to_build_args(context, &self.image_name))
+    }
+
+    pub fn container_spec(
+        &self,
+        context: &Context,
+        mounts: &[DockerMount],
+    ) -> Result<ContainerSpec, Error> {
+        let main_port = PortMapping {
+            listen_host: self.listen_host.clone(),
+            listen_port: self.listen_port,
+            container_port: self.container_port,
+            protocol: PortProtocol::Tcp,
+        };
+        let ports = std::iter::once(&main_port)
+            .chain(&self.options.ports)
+            .map(PortMapping::resolve)
+            .collect::<Result<_, _>>()?;
+        let mut security_opt = self.options.security_opt.clone();
+        if self.options.disable_selinux_labels {
+            security_opt.push("label=disable".to_owned());
+        }
+
+        Ok(ContainerSpec {
+            name: self.container_name.clone(),
+            image: self.image_name.clone(),
+            labels: BTreeMap::from([
+                (LABEL_OWNER.to_owned(), "1".to_owned()),
+                (LABEL_TUNNEL.to_owned(), self.name().to_owned()),
+                (LABEL_CONFIG.to_owned(), self.config_id.clone()),
+            ]),
+            auto_remove: !self.options.keep_container && self.runtime.supports_detached_rm(),
+            ports,
+            mounts: mounts
+                .iter()
+                .chain(&self.options.mounts)
+                .map(|mount| BindMount {
+                    source: context.apply_path(&mount.host_endpoint).to_string_lossy().into_owned(),
+                    target: mount.container_endpoint.to_string_lossy().into_owned(),
+                    read_only: mount.read_only,
+                })
+                .collect(),
+            devices: self.options.devices.clone(),
+            cap_add: self.options.cap_add.clone(),
+            cap_drop: self.options.cap_drop.clone(),
+            security_opt,
+            sysctls: self.options.sysctls.clone(),
+            read_only: self.options.read_only,
+            user: self.options.user.clone(),
+            environment: self
+                .options
+                .environment
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), value.resolve(context)?)))
+                .collect::<Result<_, Error>>()?,
+            network: self.options.network.clone(),
+            dns: self.options.dns.clone(),
+            command: Vec::new(),
+            extra_args: self.options.extra_run_args.clone(),
+        })
+    }
+
+    /// Fails if the host side of a mount does not exist, which would otherwise
+    /// surface as a bind mount error of the runtime.
+    pub fn check_mounts(&self, context: &Context, mounts: &[DockerMount]) -> Result<(), Error> {
         for mount in mounts {
-            args.push("--mount".to_owned());
-            args.push(format!(
-                "type=bind,source={},destination={},readonly=true",
-                context.apply_path(&mount.host_endpoint).to_string_lossy(),
-                mount.container_endpoint.to_string_lossy(),
-            ));
+            let file_path = context.apply_path(&mount.host_endpoint);
+            if !file_path.exists() {
+                return Err(Error::FileNotFound { tunnel: self.name().to_owned(), file_path });
+            }
         }
+        Ok(())
+    }
+
+    fn with_backend<T>(
+        &self,
+        api: impl FnOnce(&DockerEngine) -> Result<T, Error>,
+        cli: impl FnOnce() -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        with_backend(self.runtime, self.engine.as_ref(), api, cli)
+    }
 
-        args.push(self.image_name.clone());
+    pub fn state(&self) -> Result<ContainerState, Error> {
+        self.with_backend(|engine| engine.state(&self.container_name), || self.cli_state())
+    }
 
-        Self::convert_output(
-            Command::new("docker")
-                .args(&args)
-                .stdin(Stdio::null())
-                .stdout(Stdio::null())
-                .stderr(Stdio::null())
-                .spawn()
-                .with_context(|_| error::SpawnDockerCommandSnafu)?
-                .wait()
-                .with_context(|_| error::WaitForDockerProcessSnafu)?,
-        )
+    /// Returns the arguments inspecting the state of the container, restricted
+    /// to containers as an image may have the same name.
+    fn state_args(&self) -> Vec<String> {
+        vec![
+            "container".to_owned(),
+            "inspect".to_owned(),
+            "--format".to_owned(),
+            "{{.State.Status}}".to_owned(),
+            self.container_name.clone(),
+        ]
     }
 
-    #[inline]
-    fn convert_output(exit_status: ExitStatus) -> Result<(), Error> {
-        match exit_status.code() {
-            Some(0) | None => Ok(()),
-            Some(code) => Err(Error::ExternalCommand { code }),
+    fn cli_state(&self) -> Result<ContainerState, Error> {
+        let args = self.state_args();
+        let output = self.run(&args)?;
+        if !output.status.success()
+            && String::from_utf8_lossy(&output.stderr).to_lowercase().contains("no such")
+        {
+            return Ok(ContainerState::Missing);
         }
+
+        convert_output(self.runtime.program(), &args, &output)?;
+        Ok(ContainerState::from_status(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// Returns the logs of the container captured with the CLI of the runtime.
+    pub fn log_output(&self) -> Result<String, Error> {
+        let args = vec!["logs".to_owned(), self.container_name.clone()];
+        let output = self.run(&args)?;
+        convert_output(self.runtime.program(), &args, &output)?;
+        Ok(format!(
+            "{}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        ))
+    }
+
+    fn remove(&self) -> Result<(), Error> {
+        remove_container(self.runtime, self.engine.as_ref(), &self.container_name)
+    }
+
+    fn run_checked(&self, args: &[String]) -> Result<(), Error> { run_checked(self.runtime, args) }
+
+    fn run(&self, args: &[String]) -> Result<Output, Error> { run(self.runtime, args) }
+}
+
+/// Lists the containers labelled by tunka, running or not.
+pub fn labelled_containers(
+    runtime: ContainerRuntime,
+    engine: Option<&DockerEngine>,
+) -> Result<Vec<LabelledContainer>, Error> {
+    with_backend(
+        runtime,
+        engine,
+        |engine| engine.labelled_containers(&format!("{LABEL_OWNER}=1")),
+        || {
+            let args = [
+                "ps".to_owned(),
+                "--all".to_owned(),
+                "--filter".to_owned(),
+                format!("label={LABEL_OWNER}=1"),
+                "--format".to_owned(),
+                "{{.Names}}".to_owned(),
+            ];
+            let output = run(runtime, &args)?;
+            convert_output(runtime.program(), &args, &output)?;
+            let names = String::from_utf8_lossy(&output.stdout);
+            let names = names.split_whitespace().map(ToOwned::to_owned).collect::<Vec<_>>();
+            if names.is_empty() {
+                return Ok(Vec::new());
+            }
+
+            // `inspect` reports the labels as a map with every runtime, unlike `ps`
+            let mut args = vec![
+                "container".to_owned(),
+                "inspect".to_owned(),
+                "--format".to_owned(),
+                format!(
+                    "{{{{.Name}}}}\t{{{{index .Config.Labels \"{LABEL_TUNNEL}\"}}}}\t{{{{index \
+                     .Config.Labels \"{LABEL_CONFIG}\"}}}}\t{{{{.State.Status}}}}"
+                ),
+            ];
+            args.extend(names);
+            let output = run(runtime, &args)?;
+            convert_output(runtime.program(), &args, &output)?;
+            Ok(parse_labelled_containers(&String::from_utf8_lossy(&output.stdout)))
+        },
+    )
+}
+
+fn parse_labelled_containers(output: &str) -> Vec<LabelledContainer> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            Some(LabelledContainer {
+                name: fields.next()?.trim_start_matches('/').to_owned(),
+                tunnel: fields.next()?.to_owned(),
+                config_id: fields.next()?.to_owned(),
+                status: fields.next()?.trim().to_owned(),
+            })
+        })
+        .collect()
+}
+
+pub fn remove_container(
+    runtime: ContainerRuntime,
+    engine: Option<&DockerEngine>,
+    container_name: &str,
+) -> Result<(), Error> {
+    with_backend(
+        runtime,
+        engine,
+        |engine| engine.remove(container_name),
+        || {
+            run_checked(
+                runtime,
+                &["rm".to_owned(), "--force".to_owned(), container_name.to_owned()],
+            )
+        },
+    )
+}
+
+/// Runs `api` against the Docker Engine if configured, `cli` otherwise or if
+/// the engine can not be reached. Other runtimes always use `cli`, the engine
+/// would run their containers on the Docker daemon.
+fn with_backend<T>(
+    runtime: ContainerRuntime,
+    engine: Option<&DockerEngine>,
+    api: impl FnOnce(&DockerEngine) -> Result<T, Error>,
+    cli: impl FnOnce() -> Result<T, Error>,
+) -> Result<T, Error> {
+    if let Some(engine) = engine.filter(|_| runtime == ContainerRuntime::Docker) {
+        match api(engine) {
+            Err(Error::ConnectDockerEngine { socket_path, source }) => {
+                tracing::warn!(
+                    "Could not connect to Docker Engine at {}, fall back to {}, error: {source}",
+                    socket_path.display(),
+                    runtime.program()
+                );
+            }
+            result => return result,
+        }
+    }
+    cli()
+}
+
+fn run_checked(runtime: ContainerRuntime, args: &[String]) -> Result<(), Error> {
+    convert_output(runtime.program(), args, &run(runtime, args)?)
+}
+
+fn run(runtime: ContainerRuntime, args: &[String]) -> Result<Output, Error> {
+    output(Command::new(runtime.program()).args(args))
+}
+
+fn output(command: &mut Command) -> Result<Output, Error> {
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|_| error::SpawnDockerCommandSnafu)?
+        .wait_with_output()
+        .with_context(|_| error::WaitForDockerProcessSnafu)
+}
+
+pub fn convert_output(program: &str, args: &[String], output: &Output) -> Result<(), Error> {
+    let stderr = || String::from_utf8_lossy(&output.stderr).trim().to_owned();
+    match (output.status.code(), output.status.signal()) {
+        (Some(0), _) => Ok(()),
+        (Some(code), _) => Err(Error::ExternalCommand {
+            program: program.to_owned(),
+            args: args.to_vec(),
+            code,
+            stderr: stderr(),
+        }),
+        (None, signal) => Err(Error::ExternalCommandTerminated {
+            program: program.to_owned(),
+            args: args.to_vec(),
+            signal: signal.unwrap_or_default(),
+            stderr: stderr(),
+        }),
     }
 }
 
@@ -94,39 +943,465 @@ impl Tunnel for DockerTunnel {
     fn tunnel_type(&self) -> TunnelType { TunnelType::Docker }
 
     #[inline]
-    fn start(&self, context: &Context) -> Result<(), Error> { self.start_with_mounts(context, &[]) }
+    fn start(&self, context: &Context) -> Result<(), Error> {
+        self.start_with_spec(context, &self.container_spec(context, &[])?)
+    }
 
     #[inline]
-    fn stop(&self, context: &Context) -> Result<(), Error> {
-        if self.is_running(context)? {
-            let exit_status = Command::new("docker")
-                .args(["stop", &self.container_name])
-                .stdin(Stdio::null())
-                .stdout(Stdio::null())
-                .stderr(Stdio::null())
-                .spawn()
-                .with_context(|_| error::SpawnDockerCommandSnafu)?
-                .wait()
-                .with_context(|_| error::WaitForDockerProcessSnafu)?;
-
-            Self::convert_output(exit_status)
-        } else {
-            Ok(())
+    fn pull(&self, _context: &Context) -> Result<(), Error> { self.pull_image() }
+
+    #[inline]
+    fn build(&self, context: &Context) -> Result<(), Error> { self.build_image(context) }
+
+    #[inline]
+    fn stop(&self, _context: &Context) -> Result<(), Error> {
+        match self.state()? {
+            ContainerState::Running | ContainerState::Restarting | ContainerState::Paused => self
+                .with_backend(
+                    |engine| engine.stop(&self.container_name),
+                    || self.run_checked(&["stop".to_owned(), self.container_name.clone()]),
+                ),
+            ContainerState::Exited => self.remove(),
+            ContainerState::Missing => Ok(()),
         }
     }
 
     #[inline]
     fn is_running(&self, _context: &Context) -> Result<bool, Error> {
-        let output = Command::new("docker")
-            .args(["inspect", "-f", "{{.State.Running}}", &self.container_name])
+        Ok(self.state()? == ContainerState::Running)
+    }
+
+    /// Prints the logs with the CLI of the runtime, which streams them to the
+    /// terminal as they come.
+    fn logs(&self, _context: &Context, follow: bool, tail: Option<usize>) -> Result<(), Error> {
+        let mut args = vec!["logs".to_owned()];
+        if follow {
+            args.push("--follow".to_owned());
+        }
+        if let Some(tail) = tail {
+            args.push(format!("--tail={tail}"));
+        }
+        args.push(self.container_name.clone());
+
+        let status = Command::new(self.runtime.program())
+            .args(&args)
             .stdin(Stdio::null())
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .spawn()
-            .with_context(|_| error::SpawnDockerCommandSnafu)?
-            .wait()
-            .with_context(|_| error::WaitForDockerProcessSnafu)?;
-
-        Ok(output.success())
+            .status()
+            .with_context(|_| error::SpawnDockerCommandSnafu)?;
+        convert_output(
+            self.runtime.program(),
+            &args,
+            &Output { status, stdout: Vec::new(), stderr: Vec::new() },
+        )
+    }
+
+    #[inline]
+    fn container(&self) -> Option<&DockerTunnel> { Some(self) }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{process::ExitStatus, time::Duration};
+
+    use super::*;
+    use crate::tunnel::docker_engine::tests::fake_engine;
+
+    fn output(status: i32, stderr: &str) -> Output {
+        Output {
+            status: ExitStatus::from_raw(status),
+            stdout: Vec::new(),
+            stderr: stderr.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_convert_output() {
+        let args = ["run".to_owned(), "--rm".to_owned(), "proxy".to_owned()];
+        assert!(convert_output("docker", &args, &output(0, "")).is_ok());
+
+        let err = convert_output(
+            "docker",
+            &args,
+            &output(125 << 8, "docker: Error response from daemon: port is already allocated.\n"),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "External command `docker run --rm proxy` failed, exit code: 125, error: docker: \
+             Error response from daemon: port is already allocated."
+        );
+
+        let err = convert_output("docker", &args, &output(9, "")).unwrap_err();
+        assert!(matches!(err, Error::ExternalCommandTerminated { signal: 9, .. }));
+    }
+
+    #[test]
+    fn test_container_state() {
+        assert_eq!(ContainerState::from_status("running\n"), ContainerState::Running);
+        assert_eq!(ContainerState::from_status("restarting"), ContainerState::Restarting);
+        assert_eq!(ContainerState::from_status("paused"), ContainerState::Paused);
+        assert_eq!(ContainerState::from_status("exited"), ContainerState::Exited);
+        assert_eq!(ContainerState::from_status("created"), ContainerState::Exited);
+        assert_eq!(ContainerState::from_status("dead"), ContainerState::Exited);
+    }
+
+    fn tunnel(runtime: ContainerRuntime) -> DockerTunnel {
+        DockerTunnel {
+            meta: TunnelMeta { name: "docker-tunnel".to_owned(), description: None },
+            runtime,
+            engine: None,
+            config_id: "0123456789abcdef".to_owned(),
+            options: ContainerOptions::default(),
+            image_name: "proxy".to_owned(),
+            container_name: "proxy".to_owned(),
+            container_port: 8118,
+            listen_host: "127.0.0.1".to_owned(),
+            listen_port: 3128,
+        }
+    }
+
+    #[test]
+    fn test_state_args() {
+        // an image of the same name must not be inspected instead
+        let tunnel = tunnel(ContainerRuntime::Docker);
+        assert_eq!(tunnel.image_name, tunnel.container_name);
+        assert_eq!(
+            tunnel.state_args(),
+            ["container", "inspect", "--format", "{{.State.Status}}", "proxy"]
+        );
+    }
+
+    #[test]
+    fn test_run_args() {
+        let context = Context::new("the-user", "/home/the-user", "/tmp/tunka");
+        let mounts = [DockerMount {
+            host_endpoint: "$HOME/vpn/config.ovpn".into(),
+            container_endpoint: "/config.ovpn".into(),
+            read_only: true,
+        }];
+
+        assert_eq!(
+            tunnel(ContainerRuntime::Docker)
+                .container_spec(&context, &mounts)
+                .unwrap()
+                .to_run_args(None),
+            [
+                "run",
+                "--detach",
+                "--rm",
+                "--name",
+                "proxy",
+                "--label",
+                "tunka=1",
+                "--label",
+                "tunka.config=0123456789abcdef",
+                "--label",
+                "tunka.tunnel=docker-tunnel",
+                "--publish",
+                "127.0.0.1:3128:8118/tcp",
+                "--mount",
+                "type=bind,source=/home/the-user/vpn/config.ovpn,destination=/config.ovpn,\
+                 readonly=true",
+                "proxy",
+            ]
+        );
+
+        let mut spec = tunnel(ContainerRuntime::Podman).container_spec(&context, &[]).unwrap();
+        spec.request_tun();
+        assert!(!spec.to_run_args(None).iter().any(|arg| arg.starts_with("--security-opt")));
+
+        let options =
+            ContainerOptions { disable_selinux_labels: true, ..ContainerOptions::default() };
+        let tunnel_with_options = DockerTunnel { options, ..tunnel(ContainerRuntime::Podman) };
+        let mut spec = tunnel_with_options.container_spec(&context, &[]).unwrap();
+        spec.request_tun();
+        spec.request_tun();
+        assert_eq!(
+            spec.to_run_args(None),
+            [
+                "run",
+                "--detach",
+                "--rm",
+                "--name",
+                "proxy",
+                "--label",
+                "tunka=1",
+                "--label",
+                "tunka.config=0123456789abcdef",
+                "--label",
+                "tunka.tunnel=docker-tunnel",
+                "--publish",
+                "127.0.0.1:3128:8118/tcp",
+                "--device=/dev/net/tun",
+                "--cap-add=NET_ADMIN",
+                "--security-opt=label=disable",
+                "proxy",
+            ]
+        );
+
+        let args = tunnel(ContainerRuntime::Nerdctl)
+            .container_spec(&context, &[])
+            .unwrap()
+            .to_run_args(None);
+        assert!(!args.contains(&"--rm".to_owned()));
+
+        let options = ContainerOptions { keep_container: true, ..ContainerOptions::default() };
+        let tunnel = DockerTunnel { options, ..tunnel(ContainerRuntime::Docker) };
+        let args = tunnel.container_spec(&context, &[]).unwrap().to_run_args(None);
+        assert!(!args.contains(&"--rm".to_owned()));
+    }
+
+    #[test]
+    fn test_create_body() {
+        let context = Context::new("the-user", "/home/the-user", "/tmp/tunka");
+        let body = tunnel(ContainerRuntime::Docker).container_spec(&context, &[]).unwrap();
+
+        assert_eq!(
+            body.to_create_body(),
+            json!({
+                "Image": "proxy",
+                "Labels": {
+                    "tunka": "1",
+                    "tunka.tunnel": "docker-tunnel",
+                    "tunka.config": "0123456789abcdef",
+                },
+                "Cmd": null,
+                "User": "",
+                "Env": [],
+                "ExposedPorts": { "8118/tcp": {} },
+                "HostConfig": {
+                    "NetworkMode": null,
+                    "Dns": [],
+                    "AutoRemove": true,
+                    "PortBindings": {
+                        "8118/tcp": [{ "HostIp": "127.0.0.1", "HostPort": "3128" }],
+                    },
+                    "Mounts": [],
+                    "Devices": [],
+                    "CapAdd": [],
+                    "CapDrop": [],
+                    "SecurityOpt": [],
+                    "Sysctls": {},
+                    "ReadonlyRootfs": false,
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_engine_backend() {
+        let (engine, requests) = fake_engine(
+            "backend",
+            vec!["HTTP/1.1 200 OK\r\n\r\n{\"State\":{\"Status\":\"exited\"}}"],
+        );
+        let docker = DockerTunnel { engine: Some(engine), ..tunnel(ContainerRuntime::Docker) };
+
+        assert_eq!(docker.state().unwrap(), ContainerState::Exited);
+        assert_eq!(requests.recv().unwrap(), "GET /containers/proxy/json HTTP/1.1");
+
+        // podman containers do not run on the Docker daemon
+        let (engine, requests) = fake_engine(
+            "backend-podman",
+            vec!["HTTP/1.1 200 OK\r\n\r\n{\"State\":{\"Status\":\"exited\"}}"],
+        );
+        let podman = DockerTunnel { engine: Some(engine), ..tunnel(ContainerRuntime::Podman) };
+        let _unused = podman.state();
+        assert!(requests.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn test_run_args_with_options() {
+        let context = Context::new("the-user", "/home/the-user", "/tmp/tunka");
+        let env_file = std::env::temp_dir().join(format!("tunka-env-{}", std::process::id()));
+        std::fs::write(&env_file, "s3cr3t\n").unwrap();
+
+        let options = ContainerOptions {
+            ports: vec![PortMapping {
+                listen_host: "127.0.0.1".to_owned(),
+                listen_port: 5353,
+                container_port: 53,
+                protocol: PortProtocol::Udp,
+            }],
+            environment: BTreeMap::from([
+                ("LISTEN".to_owned(), EnvironmentValue::Value("0.0.0.0".to_owned())),
+                ("PASSWORD".to_owned(), EnvironmentValue::File { file: env_file.clone() }),
+            ]),
+            mounts: vec![DockerMount {
+                host_endpoint: "$HOME/privoxy".into(),
+                container_endpoint: "/etc/privoxy".into(),
+                read_only: false,
+            }],
+            network: Some("proxies".to_owned()),
+            dns: vec!["1.1.1.1".to_owned()],
+            cap_drop: vec!["ALL".to_owned()],
+            read_only: true,
+            user: Some("nobody".to_owned()),
+            extra_run_args: vec!["--memory=64m".to_owned()],
+            ..ContainerOptions::default()
+        };
+        let tunnel = DockerTunnel { options, ..tunnel(ContainerRuntime::Docker) };
+        let args = tunnel
+            .container_spec(&context, &[])
+            .unwrap()
+            .to_run_args(Some(Path::new("/tmp/tunka/proxy.env")));
+        std::fs::remove_file(env_file).unwrap();
+
+        assert_eq!(
+            args,
+            [
+                "run",
+                "--detach",
+                "--rm",
+                "--name",
+                "proxy",
+                "--label",
+                "tunka=1",
+                "--label",
+                "tunka.config=0123456789abcdef",
+                "--label",
+                "tunka.tunnel=docker-tunnel",
+                "--publish",
+                "127.0.0.1:3128:8118/tcp",
+                "--publish",
+                "127.0.0.1:5353:53/udp",
+                "--cap-drop=ALL",
+                "--read-only",
+                "--user=nobody",
+                "--network=proxies",
+                "--dns=1.1.1.1",
+                "--env-file",
+                "/tmp/tunka/proxy.env",
+                "--mount",
+                "type=bind,source=/home/the-user/privoxy,destination=/etc/privoxy,readonly=false",
+                "--memory=64m",
+                "proxy",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_run_command_environment() {
+        let dir = std::env::temp_dir().join(format!("tunka-run-env-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let context = Context::new("the-user", "/home/the-user", &dir);
+        let tunnel = tunnel(ContainerRuntime::Docker);
+        let mut spec = tunnel.container_spec(&context, &[]).unwrap();
+        spec.environment = vec![
+            ("DOCKER_HOST".to_owned(), "tcp://192.0.2.1:2375".to_owned()),
+            ("PASSWORD".to_owned(), "hunter2".to_owned()),
+        ];
+
+        let (command, args, env_file) = tunnel.run_command(&context, &spec).unwrap();
+        // neither reaches the runtime itself
+        assert_eq!(command.get_envs().count(), 0);
+        assert!(!args.iter().any(|arg| arg.contains("192.0.2.1") || arg.contains("hunter2")));
+        let env_file = env_file.unwrap();
+        assert!(args
+            .windows(2)
+            .any(|pair| pair[0] == "--env-file" && Path::new(&pair[1]) == env_file.path()));
+        assert_eq!(
+            std::fs::read_to_string(env_file.path()).unwrap(),
+            "DOCKER_HOST=tcp://192.0.2.1:2375\nPASSWORD=hunter2\n"
+        );
+
+        spec.environment = vec![("KEY".to_owned(), "-----BEGIN\nabc".to_owned())];
+        assert!(matches!(
+            tunnel.run_command(&context, &spec),
+            Err(Error::MultilineEnvironmentValue { name }) if name == "KEY"
+        ));
+        drop(env_file);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_environment_value() {
+        let environment: BTreeMap<String, EnvironmentValue> = serde_yaml::from_str(
+            r"
+            PORT: 8118
+            DEBUG: true
+            NAME: proxy
+            PASSWORD:
+              file: /run/secrets/password
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(
+            environment,
+            BTreeMap::from([
+                ("DEBUG".to_owned(), EnvironmentValue::Value("true".to_owned())),
+                ("NAME".to_owned(), EnvironmentValue::Value("proxy".to_owned())),
+                (
+                    "PASSWORD".to_owned(),
+                    EnvironmentValue::File { file: "/run/secrets/password".into() }
+                ),
+                ("PORT".to_owned(), EnvironmentValue::Value("8118".to_owned())),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_build_args() {
+        let context = Context::new("the-user", "/home/the-user", "/tmp/tunka");
+        let build = ImageBuild {
+            context: "$HOME/proxy".into(),
+            dockerfile: Some("$HOME/proxy/Dockerfile.alpine".into()),
+            build_args: BTreeMap::from([("VERSION".to_owned(), "3.0".to_owned())]),
+        };
+
+        assert_eq!(
+            build.to_build_args(&context, "proxy:local"),
+            [
+                "build",
+                "--tag",
+                "proxy:local",
+                "--file",
+                "/home/the-user/proxy/Dockerfile.alpine",
+                "--build-arg",
+                "VERSION=3.0",
+                "/home/the-user/proxy",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_prepare_image_never_pull() {
+        let (engine, _requests) = fake_engine(
+            "never-pull",
+            vec!["HTTP/1.1 404 Not Found\r\n\r\n{\"message\":\"No such image\"}"],
+        );
+        let options = ContainerOptions { pull: PullPolicy::Never, ..ContainerOptions::default() };
+        let tunnel =
+            DockerTunnel { engine: Some(engine), options, ..tunnel(ContainerRuntime::Docker) };
+        let context = Context::new("the-user", "/home/the-user", "/tmp/tunka");
+
+        assert!(matches!(tunnel.prepare_image(&context), Err(Error::ImageNotFound { .. })));
+    }
+
+    #[test]
+    fn test_orphans() {
+        let output = [
+            "/proxy\tdocker-tunnel\t0123456789abcdef\trunning",
+            "/old-proxy\told-tunnel\t0123456789abcdef\texited",
+            "/other\tother-tunnel\tfedcba9876543210\trunning",
+        ];
+        let containers = parse_labelled_containers(&output.join("\n"));
+        assert_eq!(
+            containers[1],
+            LabelledContainer {
+                name: "old-proxy".to_owned(),
+                tunnel: "old-tunnel".to_owned(),
+                config_id: "0123456789abcdef".to_owned(),
+                status: "exited".to_owned(),
+            }
+        );
+
+        let tunnel = tunnel(ContainerRuntime::Docker);
+        let orphans = containers
+            .iter()
+            .filter(|container| container.is_orphan(&tunnel.config_id, &[&tunnel]))
+            .map(|container| container.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(orphans, ["old-proxy"]);
     }
 }
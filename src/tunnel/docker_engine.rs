@@ -0,0 +1,496 @@
+use std::{
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde_json::{json, Value};
+use snafu::ResultExt;
+
+use crate::{
+    error::{self, Error},
+    tunnel::docker::{
+        ContainerSpec, ContainerState, LabelledContainer, LABEL_CONFIG, LABEL_TUNNEL,
+    },
+};
+
+const DEFAULT_SOCKET_PATH: &str = "/var/run/docker.sock";
+
+/// Longest wait for a single read or write, a pull keeps sending progress
+/// while it runs.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A minimal client of the Docker Engine API, speaking HTTP/1.1 over a unix
+/// socket.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DockerEngine {
+    socket_path: PathBuf,
+    timeout: Duration,
+}
+
+impl DockerEngine {
+    #[inline]
+    pub fn new<P: AsRef<Path>>(socket_path: P) -> Self {
+        Self { socket_path: socket_path.as_ref().to_owned(), timeout: DEFAULT_TIMEOUT }
+    }
+
+    /// Locates the engine socket from `$DOCKER_HOST`, falling back to
+    /// `/var/run/docker.sock`. Returns `None` if `$DOCKER_HOST` does not point
+    /// to a unix socket.
+    pub fn from_env() -> Option<Self> {
+        match std::env::var("DOCKER_HOST") {
+            Ok(host) if !host.is_empty() => host.strip_prefix("unix://").map(Self::new),
+            _ => Some(Self::new(DEFAULT_SOCKET_PATH)),
+        }
+    }
+
+    pub fn state(&self, container_name: &str) -> Result<ContainerState, Error> {
+        let path = format!("/containers/{container_name}/json");
+        match self.request("GET", &path, None)? {
+            (404, _) => Ok(ContainerState::Missing),
+            (200, body) => Ok(ContainerState::from_status(
+                body.pointer("/State/Status").and_then(Value::as_str).unwrap_or_default(),
+            )),
+            (status, body) => Err(response_error("GET", &path, status, &body)),
+        }
+    }
+
+    /// Creates and starts a container, returns the ID of the container.
+    pub fn run(&self, spec: &ContainerSpec) -> Result<String, Error> {
+        let path = format!("/containers/create?name={}", spec.name);
+        let id = match self.request("POST", &path, Some(&spec.to_create_body()))? {
+            (201, body) => body["Id"].as_str().unwrap_or(&spec.name).to_owned(),
+            (status, body) => return Err(response_error("POST", &path, status, &body)),
+        };
+
+        let path = format!("/containers/{id}/start");
+        match self.request("POST", &path, None)? {
+            (204 | 304, _) => Ok(id),
+            (status, body) => Err(response_error("POST", &path, status, &body)),
+        }
+    }
+
+    pub fn stop(&self, container_name: &str) -> Result<(), Error> {
+        let path = format!("/containers/{container_name}/stop");
+        match self.request("POST", &path, None)? {
+            (204 | 304 | 404, _) => Ok(()),
+            (status, body) => Err(response_error("POST", &path, status, &body)),
+        }
+    }
+
+    pub fn remove(&self, container_name: &str) -> Result<(), Error> {
+        let path = format!("/containers/{container_name}?force=true");
+        match self.request("DELETE", &path, None)? {
+            (204 | 404, _) => Ok(()),
+            (status, body) => Err(response_error("DELETE", &path, status, &body)),
+        }
+    }
+
+    /// Lists all containers carrying the label `label`, given as `key=value`.
+    pub fn labelled_containers(&self, label: &str) -> Result<Vec<LabelledContainer>, Error> {
+        let filters = json!({ "label": [label] }).to_string();
+        let path = format!("/containers/json?all=true&filters={}", percent_encode(&filters));
+        match self.request("GET", &path, None)? {
+            (200, Value::Array(containers)) => Ok(containers
+                .iter()
+                .map(|container| {
+                    let label = |key: &str| {
+                        container["Labels"][key].as_str().unwrap_or_default().to_owned()
+                    };
+                    LabelledContainer {
+                        name: container["Names"][0]
+                            .as_str()
+                            .unwrap_or_default()
+                            .trim_start_matches('/')
+                            .to_owned(),
+                        tunnel: label(LABEL_TUNNEL),
+                        config_id: label(LABEL_CONFIG),
+                        status: container["State"].as_str().unwrap_or_default().to_owned(),
+                    }
+                })
+                .collect()),
+            (status, body) => Err(response_error("GET", &path, status, &body)),
+        }
+    }
+
+    pub fn image_exists(&self, image: &str) -> Result<bool, Error> {
+        let path = format!("/images/{image}/json");
+        match self.request("GET", &path, None)? {
+            (200, _) => Ok(true),
+            (404, _) => Ok(false),
+            (status, body) => Err(response_error("GET", &path, status, &body)),
+        }
+    }
+
+    /// Returns the entrypoint of an image, empty if it has none.
+    pub fn image_entrypoint(&self, image: &str) -> Result<Vec<String>, Error> {
+        let path = format!("/images/{image}/json");
+        match self.request("GET", &path, None)? {
+            (200, body) => Ok(serde_json::from_value(body["Config"]["Entrypoint"].clone())
+                .ok()
+                .flatten()
+                .unwrap_or_default()),
+            (status, body) => Err(response_error("GET", &path, status, &body)),
+        }
+    }
+
+    pub fn pull(&self, image: &str) -> Result<(), Error> {
+        let (name, tag) = split_image(image);
+        let path = format!(
+            "/images/create?fromImage={}&tag={}",
+            percent_encode(name),
+            percent_encode(tag)
+        );
+        let (status, body) = self.request_raw("POST", &path, None)?;
+        if status != 200 {
+            return Err(response_error("POST", &path, status, &json_body(&body)));
+        }
+
+        // the progress is streamed as JSON objects, failures are reported by one of
+        // them while the status code is already sent
+        let failure = serde_json::Deserializer::from_slice(&body)
+            .into_iter::<Value>()
+            .filter_map(Result::ok)
+            .find(|progress| progress.get("error").is_some());
+        failure.map_or(Ok(()), |progress| {
+            Err(response_error("POST", &path, status, &json!({ "message": progress["error"] })))
+        })
+    }
+
+    fn request(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<(u16, Value), Error> {
+        let (status, body) = self.request_raw(method, path, body)?;
+        Ok((status, json_body(&body)))
+    }
+
+    fn request_raw(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<(u16, Vec<u8>), Error> {
+        let mut stream = UnixStream::connect(&self.socket_path).with_context(|_| {
+            error::ConnectDockerEngineSnafu { socket_path: self.socket_path.clone() }
+        })?;
+        // a stalled daemon must not hang tunka
+        stream.set_read_timeout(Some(self.timeout)).context(error::DockerEngineIoSnafu)?;
+        stream.set_write_timeout(Some(self.timeout)).context(error::DockerEngineIoSnafu)?;
+
+        let body = body.map(Value::to_string).unwrap_or_default();
+        let request = format!(
+            "{method} {path} HTTP/1.1\r\nHost: docker\r\nConnection: close\r\nContent-Type: \
+             application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        stream.write_all(request.as_bytes()).context(error::DockerEngineIoSnafu)?;
+
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response).context(error::DockerEngineIoSnafu)?;
+        parse_response(&response)
+    }
+}
+
+/// Splits an image reference into the repository and the tag or digest,
+/// without a tag the Engine API would pull every tag of the repository.
+fn split_image(image: &str) -> (&str, &str) {
+    if let Some((name, digest)) = image.split_once('@') {
+        return (name, digest);
+    }
+    match image.rsplit_once(':') {
+        Some((name, tag)) if !tag.contains('/') => (name, tag),
+        _ => (image, "latest"),
+    }
+}
+
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b"-_.~".contains(&b) {
+                char::from(b).to_string()
+            } else {
+                format!("%{b:02X}")
+            }
+        })
+        .collect()
+}
+
+fn response_error(method: &str, path: &str, status: u16, body: &Value) -> Error {
+    let message = body["message"].as_str().map_or_else(|| body.to_string(), ToOwned::to_owned);
+    Error::DockerEngineResponse {
+        method: method.to_owned(),
+        path: path.to_owned(),
+        status,
+        message,
+    }
+}
+
+fn parse_response(response: &[u8]) -> Result<(u16, Vec<u8>), Error> {
+    let invalid =
+        |message: &str| Error::InvalidDockerEngineResponse { message: message.to_owned() };
+
+    let header_end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| invalid("missing end of headers"))?;
+    let head = String::from_utf8_lossy(&response[..header_end]);
+    let mut body = response[header_end + 4..].to_vec();
+
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| invalid("malformed status line"))?;
+
+    let chunked = lines.any(|line| {
+        line.split_once(':').is_some_and(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("transfer-encoding")
+                && value.trim().eq_ignore_ascii_case("chunked")
+        })
+    });
+    if chunked {
+        body = decode_chunked(&body).ok_or_else(|| invalid("malformed chunked body"))?;
+    }
+
+    Ok((status, body))
+}
+
+fn json_body(body: &[u8]) -> Value {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        Value::Null
+    } else {
+        serde_json::from_slice(body).unwrap_or_else(
+            // plain text error messages are wrapped so they can be reported like JSON ones
+            |_| json!({ "message": String::from_utf8_lossy(body).trim() }),
+        )
+    }
+}
+
+fn decode_chunked(mut data: &[u8]) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = data.windows(2).position(|w| w == b"\r\n")?;
+        let size = std::str::from_utf8(&data[..line_end]).ok()?;
+        let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Some(body);
+        }
+        body.extend_from_slice(data.get(..size)?);
+        data = data.get(size + 2..)?;
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        os::unix::net::UnixListener,
+        sync::mpsc,
+        thread,
+    };
+
+    use super::*;
+
+    /// Serves the canned `responses` one connection each on a unix socket,
+    /// the received request lines are sent to the returned channel.
+    pub fn fake_engine(
+        name: &str,
+        responses: Vec<&'static str>,
+    ) -> (DockerEngine, mpsc::Receiver<String>) {
+        let socket_path =
+            std::env::temp_dir().join(format!("tunka-{name}-{}.sock", std::process::id()));
+        let _unused = std::fs::remove_file(&socket_path);
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        let (sender, receiver) = mpsc::channel();
+
+        let _handle = thread::spawn(move || {
+            for response in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut request_line = String::new();
+                let _ = reader.read_line(&mut request_line).unwrap();
+
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    let _ = reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+
+                sender
+                    .send(format!("{}{}", request_line.trim(), String::from_utf8(body).unwrap()))
+                    .unwrap();
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        (DockerEngine::new(socket_path), receiver)
+    }
+
+    #[test]
+    fn test_state() {
+        let (engine, requests) = fake_engine(
+            "state",
+            vec![
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nTransfer-Encoding: \
+                 chunked\r\n\r\n1c\r\n{\"State\":{\"Status\":\"running\"\r\n2\r\n}}\r\n0\r\n\r\n",
+                "HTTP/1.1 404 Not Found\r\nContent-Type: \
+                 application/json\r\n\r\n{\"message\":\"No such container: proxy\"}",
+            ],
+        );
+
+        assert_eq!(engine.state("proxy").unwrap(), ContainerState::Running);
+        assert_eq!(engine.state("proxy").unwrap(), ContainerState::Missing);
+        assert_eq!(requests.recv().unwrap(), "GET /containers/proxy/json HTTP/1.1");
+    }
+
+    #[test]
+    fn test_timeout() {
+        let socket_path =
+            std::env::temp_dir().join(format!("tunka-stalled-{}.sock", std::process::id()));
+        let _unused = std::fs::remove_file(&socket_path);
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        let _handle = thread::spawn(move || {
+            // accepts, reads nothing and never answers
+            let (_stream, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_secs(5));
+        });
+        let engine = DockerEngine { socket_path, timeout: Duration::from_millis(100) };
+
+        assert!(matches!(engine.state("proxy"), Err(Error::DockerEngineIo { .. })));
+    }
+
+    #[test]
+    fn test_image_entrypoint() {
+        let (engine, requests) = fake_engine(
+            "image-entrypoint",
+            vec![
+                "HTTP/1.1 200 OK\r\n\r\n{\"Config\":{\"Entrypoint\":[\"/usr/sbin/openconnect\"]}}",
+                "HTTP/1.1 200 OK\r\n\r\n{\"Config\":{\"Entrypoint\":null}}",
+            ],
+        );
+
+        assert_eq!(engine.image_entrypoint("openconnect").unwrap(), ["/usr/sbin/openconnect"]);
+        assert_eq!(engine.image_entrypoint("openconnect").unwrap(), Vec::<String>::new());
+        assert_eq!(requests.recv().unwrap(), "GET /images/openconnect/json HTTP/1.1");
+    }
+
+    #[test]
+    fn test_run() {
+        let (engine, requests) = fake_engine(
+            "run",
+            vec![
+                "HTTP/1.1 201 Created\r\nContent-Length: 13\r\n\r\n{\"Id\":\"abc\"}\n",
+                "HTTP/1.1 204 No Content\r\n\r\n",
+            ],
+        );
+        let spec = ContainerSpec {
+            name: "proxy".to_owned(),
+            image: "privoxy".to_owned(),
+            ..ContainerSpec::default()
+        };
+
+        assert_eq!(engine.run(&spec).unwrap(), "abc");
+        let create = requests.recv().unwrap();
+        assert!(create.starts_with("POST /containers/create?name=proxy HTTP/1.1{"));
+        assert!(create.contains("\"Image\":\"privoxy\""));
+        assert_eq!(requests.recv().unwrap(), "POST /containers/abc/start HTTP/1.1");
+    }
+
+    #[test]
+    fn test_error_response() {
+        let (engine, _requests) = fake_engine(
+            "error",
+            vec![
+                "HTTP/1.1 500 Internal Server Error\r\n\r\n{\"message\":\"driver failed \
+                 programming external connectivity: port is already allocated\"}",
+            ],
+        );
+
+        assert_eq!(
+            engine.stop("proxy").unwrap_err().to_string(),
+            "Docker Engine API request POST /containers/proxy/stop failed, status: 500, error: \
+             driver failed programming external connectivity: port is already allocated"
+        );
+    }
+
+    #[test]
+    fn test_connect_error() {
+        let engine = DockerEngine::new("/nonexistent/docker.sock");
+        assert!(matches!(engine.state("proxy"), Err(Error::ConnectDockerEngine { .. })));
+    }
+
+    #[test]
+    fn test_pull() {
+        let (engine, requests) = fake_engine(
+            "pull",
+            vec![
+                "HTTP/1.1 200 OK\r\n\r\n{\"status\":\"Pulling from \
+                 library/privoxy\"}\n{\"status\":\"Downloaded newer image\"}\n",
+                "HTTP/1.1 200 \
+                 OK\r\n\r\n{\"status\":\"Pulling\"}\n{\"errorDetail\":{\"message\":\"manifest \
+                 unknown\"},\"error\":\"manifest unknown\"}\n",
+            ],
+        );
+
+        engine.pull("privoxy:latest").unwrap();
+        assert_eq!(
+            engine.pull("privoxy:missing").unwrap_err().to_string(),
+            "Docker Engine API request POST /images/create?fromImage=privoxy&tag=missing failed, \
+             status: 200, error: manifest unknown"
+        );
+        assert_eq!(
+            requests.recv().unwrap(),
+            "POST /images/create?fromImage=privoxy&tag=latest HTTP/1.1"
+        );
+    }
+
+    #[test]
+    fn test_split_image() {
+        assert_eq!(split_image("privoxy"), ("privoxy", "latest"));
+        assert_eq!(split_image("dperson/torproxy:1.2"), ("dperson/torproxy", "1.2"));
+        assert_eq!(split_image("registry:5000/team/vpn"), ("registry:5000/team/vpn", "latest"));
+        assert_eq!(split_image("registry:5000/team/vpn:edge"), ("registry:5000/team/vpn", "edge"));
+        assert_eq!(split_image("alpine@sha256:abc"), ("alpine", "sha256:abc"));
+    }
+
+    #[test]
+    fn test_labelled_containers() {
+        let (engine, requests) = fake_engine(
+            "labelled-containers",
+            vec![
+                "HTTP/1.1 200 \
+                 OK\r\n\r\n[{\"Names\":[\"/old-proxy\"],\"State\":\"exited\",\"Labels\":{\"tunka\"\
+                 :\"1\",\"tunka.tunnel\":\"old-tunnel\",\"tunka.config\":\"0123456789abcdef\"}}]",
+            ],
+        );
+
+        assert_eq!(
+            engine.labelled_containers("tunka=1").unwrap(),
+            [LabelledContainer {
+                name: "old-proxy".to_owned(),
+                tunnel: "old-tunnel".to_owned(),
+                config_id: "0123456789abcdef".to_owned(),
+                status: "exited".to_owned(),
+            }]
+        );
+        assert_eq!(
+            requests.recv().unwrap(),
+            "GET /containers/json?all=true&filters=%7B%22label%22%3A%5B%22tunka%3D1%22%5D%7D \
+             HTTP/1.1"
+        );
+    }
+}
@@ -0,0 +1,334 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    context::Context,
+    error::Error,
+    tunnel::{
+        docker::{ContainerSpec, DockerMount},
+        secret::{credentials_file_name, SecretFile},
+        DockerTunnel, SecretSource, Tunnel, TunnelMeta, TunnelType,
+    },
+};
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OpenConnectProtocol {
+    #[default]
+    AnyConnect,
+    GlobalProtect,
+    Pulse,
+    Fortinet,
+    F5,
+}
+
+impl OpenConnectProtocol {
+    /// Returns the name `openconnect --protocol` takes.
+    #[inline]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::AnyConnect => "anyconnect",
+            Self::GlobalProtect => "gp",
+            Self::Pulse => "pulse",
+            Self::Fortinet => "fortinet",
+            Self::F5 => "f5",
+        }
+    }
+}
+
+/// How to log in to the server. The password and the OTP are written to a
+/// file mounted at `password_target`. A detached container has no stdin, so
+/// the entrypoint of the image has to feed the file to
+/// `openconnect --passwd-on-stdin`, an image running openconnect directly is
+/// refused.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct OpenConnectOptions {
+    #[serde(default)]
+    pub protocol: OpenConnectProtocol,
+    #[serde(default)]
+    pub user_name: Option<String>,
+    #[serde(default)]
+    pub password: Option<SecretSource>,
+    #[serde(default)]
+    pub otp: Option<SecretSource>,
+    /// Pin of the server certificate, e.g. `pin-sha256:...`.
+    #[serde(default)]
+    pub server_cert: Option<String>,
+    #[serde(default = "default_password_target")]
+    pub password_target: PathBuf,
+    #[serde(default)]
+    pub openconnect_args: Vec<String>,
+    /// Passes the arguments of `openconnect` joined by spaces in this
+    /// environment variable instead of as the container command.
+    #[serde(default)]
+    pub openconnect_args_env: Option<String>,
+}
+
+fn default_password_target() -> PathBuf { PathBuf::from("/run/secrets/openconnect") }
+
+impl Default for OpenConnectOptions {
+    fn default() -> Self {
+        Self {
+            protocol: OpenConnectProtocol::default(),
+            user_name: None,
+            password: None,
+            otp: None,
+            server_cert: None,
+            password_target: default_password_target(),
+            openconnect_args: Vec::new(),
+            openconnect_args_env: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DockerOpenConnectTunnel {
+    pub docker_tunnel: DockerTunnel,
+    pub server: String,
+    pub openconnect: OpenConnectOptions,
+}
+
+impl DockerOpenConnectTunnel {
+    pub fn openconnect_args(&self) -> Vec<String> {
+        let options = &self.openconnect;
+        let mut args = vec![format!("--protocol={}", options.protocol.as_str())];
+        if let Some(user_name) = &options.user_name {
+            args.push(format!("--user={user_name}"));
+        }
+        if let Some(server_cert) = &options.server_cert {
+            args.push(format!("--servercert={server_cert}"));
+        }
+        if options.password.is_some() || options.otp.is_some() {
+            args.push("--passwd-on-stdin".to_owned());
+        }
+        args.extend(options.openconnect_args.iter().cloned());
+        args.push(self.server.clone());
+        args
+    }
+
+    fn container_spec(
+        &self,
+        context: &Context,
+        password_file: Option<&Path>,
+    ) -> Result<ContainerSpec, Error> {
+        let mounts = password_file
+            .map(|password_file| DockerMount {
+                host_endpoint: password_file.to_owned(),
+                container_endpoint: self.openconnect.password_target.clone(),
+                read_only: true,
+            })
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        let mut spec = self.docker_tunnel.container_spec(context, &mounts)?;
+        spec.request_tun();
+        spec.pass_arguments(
+            &self.openconnect_args(),
+            self.openconnect.openconnect_args_env.as_ref(),
+        );
+        Ok(spec)
+    }
+
+    /// Checks that the image runs openconnect as configured, before it logs
+    /// in to the server, as failed logins may lock the account.
+    fn check_entrypoint(&self, spec: &ContainerSpec, feeds_password: bool) -> Result<(), Error> {
+        let entrypoint = self.docker_tunnel.image_entrypoint()?;
+        let tunnel = self.name().to_owned();
+        let image = self.docker_tunnel.image_name.clone();
+        if entrypoint.is_empty() && !spec.command.is_empty() {
+            return Err(Error::OpenConnectNoEntrypoint { tunnel, image });
+        }
+        if feeds_password && runs_openconnect(&entrypoint) {
+            let password_target = self.openconnect.password_target.clone();
+            return Err(Error::OpenConnectEntrypoint { tunnel, image, password_target });
+        }
+        Ok(())
+    }
+
+    /// Returns the lines answering the password and OTP prompts.
+    fn password_lines(&self, context: &Context) -> Result<Option<String>, Error> {
+        let secrets = [&self.openconnect.password, &self.openconnect.otp];
+        let lines = secrets
+            .into_iter()
+            .flatten()
+            .map(|secret| Ok(format!("{}\n", secret.resolve(context)?)))
+            .collect::<Result<String, Error>>()?;
+        Ok(Some(lines).filter(|lines| !lines.is_empty()))
+    }
+}
+
+fn runs_openconnect(entrypoint: &[String]) -> bool {
+    entrypoint
+        .first()
+        .is_some_and(|program| Path::new(program).file_name() == Some("openconnect".as_ref()))
+}
+
+impl Tunnel for DockerOpenConnectTunnel {
+    #[inline]
+    fn meta(&self) -> &TunnelMeta { self.docker_tunnel.meta() }
+
+    #[inline]
+    fn tunnel_type(&self) -> TunnelType { TunnelType::DockerOpenConnect }
+
+    #[inline]
+    fn start(&self, context: &Context) -> Result<(), Error> {
+        if self.docker_tunnel.is_running(context)? {
+            return Ok(());
+        }
+        self.clean_up(context);
+
+        let password_file = match self.password_lines(context)? {
+            Some(lines) => {
+                Some(SecretFile::create(context, &credentials_file_name(self.name()), &lines)?)
+            }
+            None => None,
+        };
+        let spec = self.container_spec(context, password_file.as_ref().map(SecretFile::path))?;
+        self.docker_tunnel.start_checked(context, &spec, || {
+            self.check_entrypoint(&spec, password_file.is_some())
+        })?;
+        // a restarted container reads the file again
+        let _unused = password_file.map(SecretFile::keep);
+        Ok(())
+    }
+
+    #[inline]
+    fn stop(&self, context: &Context) -> Result<(), Error> {
+        self.docker_tunnel.stop(context)?;
+        self.clean_up(context);
+        Ok(())
+    }
+
+    #[inline]
+    fn clean_up(&self, context: &Context) {
+        SecretFile::remove(context, &credentials_file_name(self.name()));
+    }
+
+    #[inline]
+    fn pull(&self, context: &Context) -> Result<(), Error> { self.docker_tunnel.pull(context) }
+
+    #[inline]
+    fn build(&self, context: &Context) -> Result<(), Error> { self.docker_tunnel.build(context) }
+
+    #[inline]
+    fn is_running(&self, context: &Context) -> Result<bool, Error> {
+        self.docker_tunnel.is_running(context)
+    }
+
+    #[inline]
+    fn logs(&self, context: &Context, follow: bool, tail: Option<usize>) -> Result<(), Error> {
+        self.docker_tunnel.logs(context, follow, tail)
+    }
+
+    #[inline]
+    fn container(&self) -> Option<&DockerTunnel> { Some(&self.docker_tunnel) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tunnel::{docker_engine::tests::fake_engine, ContainerOptions, ContainerRuntime};
+
+    fn tunnel(openconnect: OpenConnectOptions) -> DockerOpenConnectTunnel {
+        DockerOpenConnectTunnel {
+            docker_tunnel: DockerTunnel {
+                meta: TunnelMeta { name: "gp".to_owned(), description: None },
+                runtime: ContainerRuntime::Docker,
+                engine: None,
+                config_id: String::new(),
+                options: ContainerOptions::default(),
+                image_name: "openconnect-proxy".to_owned(),
+                container_name: "gp".to_owned(),
+                container_port: 8888,
+                listen_host: "127.0.0.1".to_owned(),
+                listen_port: 8888,
+            },
+            server: "https://vpn.example.com".to_owned(),
+            openconnect,
+        }
+    }
+
+    #[test]
+    fn test_openconnect_args() {
+        let context = Context::new("the-user", "/home/the-user", "/tmp/tunka");
+        let tunnel = tunnel(OpenConnectOptions {
+            protocol: OpenConnectProtocol::GlobalProtect,
+            user_name: Some("the-user".to_owned()),
+            password: Some(SecretSource::Command("echo hunter2".to_owned())),
+            otp: Some(SecretSource::Command("echo 123456".to_owned())),
+            server_cert: Some("pin-sha256:AAAA".to_owned()),
+            ..OpenConnectOptions::default()
+        });
+
+        assert_eq!(
+            tunnel.openconnect_args(),
+            [
+                "--protocol=gp",
+                "--user=the-user",
+                "--servercert=pin-sha256:AAAA",
+                "--passwd-on-stdin",
+                "https://vpn.example.com",
+            ]
+        );
+        assert_eq!(tunnel.password_lines(&context).unwrap().unwrap(), "hunter2\n123456\n");
+
+        let spec = tunnel.container_spec(&context, Some(Path::new("/tmp/tunka/gp.password")));
+        let spec = spec.unwrap();
+        assert_eq!(spec.mounts[0].target, "/run/secrets/openconnect");
+        assert_eq!(spec.command.last().unwrap(), "https://vpn.example.com");
+        assert!(spec.devices.contains(&"/dev/net/tun".to_owned()));
+    }
+
+    #[test]
+    fn test_check_entrypoint() {
+        let (engine, _requests) = fake_engine(
+            "openconnect-entrypoint",
+            vec![
+                "HTTP/1.1 200 OK\r\n\r\n{\"Config\":{\"Entrypoint\":[\"/entrypoint.sh\"]}}",
+                "HTTP/1.1 200 OK\r\n\r\n{\"Config\":{\"Entrypoint\":[\"openconnect\"]}}",
+                "HTTP/1.1 200 OK\r\n\r\n{\"Config\":{\"Entrypoint\":null}}",
+            ],
+        );
+        let mut tunnel = tunnel(OpenConnectOptions::default());
+        tunnel.docker_tunnel.engine = Some(engine);
+        let context = Context::new("the-user", "/home/the-user", "/tmp/tunka");
+        let spec = tunnel.container_spec(&context, None).unwrap();
+
+        tunnel.check_entrypoint(&spec, true).unwrap();
+        assert!(matches!(
+            tunnel.check_entrypoint(&spec, true),
+            Err(Error::OpenConnectEntrypoint { .. })
+        ));
+        assert!(matches!(
+            tunnel.check_entrypoint(&spec, false),
+            Err(Error::OpenConnectNoEntrypoint { .. })
+        ));
+    }
+
+    #[test]
+    fn test_runs_openconnect() {
+        assert!(runs_openconnect(&["/usr/sbin/openconnect".to_owned()]));
+        assert!(runs_openconnect(&["openconnect".to_owned(), "--verbose".to_owned()]));
+        assert!(!runs_openconnect(&["/entrypoint.sh".to_owned()]));
+        assert!(!runs_openconnect(&[]));
+    }
+
+    #[test]
+    fn test_without_password() {
+        let context = Context::new("the-user", "/home/the-user", "/tmp/tunka");
+        let tunnel = tunnel(OpenConnectOptions {
+            openconnect_args_env: Some("OPENCONNECT_OPTIONS".to_owned()),
+            ..OpenConnectOptions::default()
+        });
+
+        assert!(tunnel.password_lines(&context).unwrap().is_none());
+        let spec = tunnel.container_spec(&context, None).unwrap();
+        assert_eq!(spec.mounts, Vec::new());
+        assert_eq!(spec.command, Vec::<String>::new());
+        assert!(spec.environment.contains(&(
+            "OPENCONNECT_OPTIONS".to_owned(),
+            "--protocol=anyconnect https://vpn.example.com".to_owned()
+        )));
+    }
+}
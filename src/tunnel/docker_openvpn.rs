@@ -1,16 +1,92 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
 
 use crate::{
     context::Context,
     error::Error,
-    tunnel::{docker::DockerMount, DockerTunnel, Tunnel, TunnelMeta, TunnelType},
+    tunnel::{
+        docker::{ContainerSpec, DockerMount},
+        secret::{credentials_file_name, SecretFile},
+        DockerTunnel, SecretSource, Tunnel, TunnelMeta, TunnelType,
+    },
 };
 
+/// Where the image expects the files of the VPN and how it takes extra
+/// arguments of `openvpn`.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct OpenVPNOptions {
+    #[serde(default = "default_config_target")]
+    pub config_target: PathBuf,
+    #[serde(default = "default_auth_target")]
+    pub auth_target: PathBuf,
+    /// CA, client certificate and key, tls-auth key and the like.
+    #[serde(default)]
+    pub extra_files: Vec<DockerMount>,
+    #[serde(default)]
+    pub openvpn_args: Vec<String>,
+    /// Passes `openvpn_args` joined by spaces in this environment variable
+    /// instead of as the container command.
+    #[serde(default)]
+    pub openvpn_args_env: Option<String>,
+}
+
+fn default_config_target() -> PathBuf { PathBuf::from("/config.ovpn") }
+
+fn default_auth_target() -> PathBuf { PathBuf::from("/auth.txt") }
+
+impl Default for OpenVPNOptions {
+    fn default() -> Self {
+        Self {
+            config_target: default_config_target(),
+            auth_target: default_auth_target(),
+            extra_files: Vec::new(),
+            openvpn_args: Vec::new(),
+            openvpn_args_env: None,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct DockerOpenVPNTunnel {
     pub docker_tunnel: DockerTunnel,
     pub config_file: PathBuf,
     pub auth_file: Option<PathBuf>,
+    /// Credentials used instead of `auth_file`, written to a file kept until
+    /// the tunnel is stopped, since a restarted container reads it again.
+    pub auth: Option<SecretSource>,
+    pub openvpn: OpenVPNOptions,
+}
+
+impl DockerOpenVPNTunnel {
+    fn mounts(&self, auth_file: Option<&Path>) -> Vec<DockerMount> {
+        let config = DockerMount {
+            host_endpoint: self.config_file.clone(),
+            container_endpoint: self.openvpn.config_target.clone(),
+            read_only: true,
+        };
+        let auth = auth_file.or(self.auth_file.as_deref()).map(|auth_file| DockerMount {
+            host_endpoint: auth_file.to_owned(),
+            container_endpoint: self.openvpn.auth_target.clone(),
+            read_only: true,
+        });
+
+        std::iter::once(config).chain(auth).chain(self.openvpn.extra_files.clone()).collect()
+    }
+
+    fn container_spec(
+        &self,
+        context: &Context,
+        auth_file: Option<&Path>,
+    ) -> Result<ContainerSpec, Error> {
+        let mounts = self.mounts(auth_file);
+        self.docker_tunnel.check_mounts(context, &mounts)?;
+
+        let mut spec = self.docker_tunnel.container_spec(context, &mounts)?;
+        spec.request_tun();
+        spec.pass_arguments(&self.openvpn.openvpn_args, self.openvpn.openvpn_args_env.as_ref());
+        Ok(spec)
+    }
 }
 
 impl Tunnel for DockerOpenVPNTunnel {
@@ -22,30 +98,145 @@ impl Tunnel for DockerOpenVPNTunnel {
 
     #[inline]
     fn start(&self, context: &Context) -> Result<(), Error> {
-        let mounts = {
-            let mut m = vec![DockerMount {
-                host_endpoint: self.config_file.clone(),
-                container_endpoint: PathBuf::from("/config.ovpn"),
-            }];
-
-            if let Some(auth_file) = &self.auth_file {
-                m.push(DockerMount {
-                    host_endpoint: auth_file.clone(),
-                    container_endpoint: PathBuf::from("/auth.txt"),
-                });
-            }
+        if self.docker_tunnel.is_running(context)? {
+            return Ok(());
+        }
+        self.clean_up(context);
 
-            m
+        let auth_file = match &self.auth {
+            Some(auth) => {
+                let credentials = format!("{}\n", auth.resolve(context)?);
+                Some(SecretFile::create(
+                    context,
+                    &credentials_file_name(self.name()),
+                    &credentials,
+                )?)
+            }
+            None => None,
         };
+        let spec = self.container_spec(context, auth_file.as_ref().map(SecretFile::path))?;
+        self.docker_tunnel.start_with_spec(context, &spec)?;
+        let _unused = auth_file.map(SecretFile::keep);
+        Ok(())
+    }
+
+    #[inline]
+    fn stop(&self, context: &Context) -> Result<(), Error> {
+        self.docker_tunnel.stop(context)?;
+        self.clean_up(context);
+        Ok(())
+    }
 
-        self.docker_tunnel.start_with_mounts(context, &mounts)
+    #[inline]
+    fn clean_up(&self, context: &Context) {
+        SecretFile::remove(context, &credentials_file_name(self.name()));
     }
 
     #[inline]
-    fn stop(&self, context: &Context) -> Result<(), Error> { self.docker_tunnel.stop(context) }
+    fn pull(&self, context: &Context) -> Result<(), Error> { self.docker_tunnel.pull(context) }
+
+    #[inline]
+    fn build(&self, context: &Context) -> Result<(), Error> { self.docker_tunnel.build(context) }
 
     #[inline]
     fn is_running(&self, context: &Context) -> Result<bool, Error> {
         self.docker_tunnel.is_running(context)
     }
+
+    #[inline]
+    fn logs(&self, context: &Context, follow: bool, tail: Option<usize>) -> Result<(), Error> {
+        self.docker_tunnel.logs(context, follow, tail)
+    }
+
+    #[inline]
+    fn container(&self) -> Option<&DockerTunnel> { Some(&self.docker_tunnel) }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::tunnel::{
+        docker_engine::tests::fake_engine, test_util::TempDir, ContainerDefaults, ContainerOptions,
+        ContainerRuntime, TunnelManager,
+    };
+
+    fn tunnel(dir: &TempDir, openvpn: OpenVPNOptions) -> DockerOpenVPNTunnel {
+        let docker_tunnel = DockerTunnel {
+            meta: TunnelMeta { name: "vpn".to_owned(), description: None },
+            runtime: ContainerRuntime::Docker,
+            engine: None,
+            config_id: String::new(),
+            options: ContainerOptions::default(),
+            image_name: "openvpn-client".to_owned(),
+            container_name: "vpn".to_owned(),
+            container_port: 3128,
+            listen_host: "127.0.0.1".to_owned(),
+            listen_port: 3128,
+        };
+        let config_file = dir.path().join("vpn.ovpn");
+        std::fs::write(&config_file, "client\n").unwrap();
+        DockerOpenVPNTunnel { docker_tunnel, config_file, auth_file: None, auth: None, openvpn }
+    }
+
+    #[test]
+    fn test_container_spec() {
+        let dir = TempDir::new("vpn-spec");
+        let context = Context::new("the-user", "/home/the-user", dir.path());
+        let openvpn = OpenVPNOptions {
+            config_target: "/vpn/client.conf".into(),
+            openvpn_args: vec!["--verb".to_owned(), "4".to_owned()],
+            ..OpenVPNOptions::default()
+        };
+        let spec = tunnel(&dir, openvpn.clone()).container_spec(&context, None).unwrap();
+        assert_eq!(spec.mounts[0].target, "/vpn/client.conf");
+        assert_eq!(spec.command, ["--verb", "4"]);
+
+        let openvpn =
+            OpenVPNOptions { openvpn_args_env: Some("OPENVPN_OPTS".to_owned()), ..openvpn };
+        let spec = tunnel(&dir, openvpn).container_spec(&context, None).unwrap();
+        assert_eq!(spec.command, Vec::<String>::new());
+        assert!(spec.environment.contains(&("OPENVPN_OPTS".to_owned(), "--verb 4".to_owned())));
+    }
+
+    #[test]
+    fn test_stop_exited() {
+        let dir = TempDir::new("vpn-stop");
+        let context = Context::new("the-user", "/home/the-user", dir.path());
+        let not_found = "HTTP/1.1 404 Not Found\r\n\r\n{\"message\":\"No such container: vpn\"}";
+        let (engine, _requests) = fake_engine("vpn-stop", vec![not_found, not_found]);
+        let mut vpn = tunnel(&dir, OpenVPNOptions::default());
+        vpn.docker_tunnel.engine = Some(engine);
+        let vpn: Box<dyn Tunnel> = Box::new(vpn);
+        let manager = TunnelManager {
+            tunnels: BTreeMap::from([("vpn".to_owned(), vpn)]),
+            container_defaults: ContainerDefaults {
+                runtime: ContainerRuntime::Docker,
+                engine: None,
+                config_id: String::new(),
+            },
+        };
+
+        // left behind by a container which exited on its own
+        let credentials = dir.path().join(credentials_file_name("vpn"));
+        std::fs::write(&credentials, "the-user\nhunter2\n").unwrap();
+        manager.stop(&context, "vpn").unwrap();
+        assert!(!credentials.exists());
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let dir = TempDir::new("vpn-missing");
+        let context = Context::new("the-user", "/home/the-user", dir.path());
+        let tunnel = DockerOpenVPNTunnel {
+            auth_file: Some("$HOME/vpn/auth.txt".into()),
+            ..tunnel(&dir, OpenVPNOptions::default())
+        };
+
+        assert_eq!(
+            tunnel.container_spec(&context, None).unwrap_err().to_string(),
+            "File /home/the-user/vpn/auth.txt of tunnel vpn does not exist"
+        );
+    }
 }
@@ -0,0 +1,132 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    context::Context,
+    error::Error,
+    tunnel::{
+        docker::{ContainerSpec, DockerMount},
+        DockerTunnel, Tunnel, TunnelMeta, TunnelType,
+    },
+};
+
+/// Where the image expects the wg-quick configuration, its file name names
+/// the interface.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct WireGuardOptions {
+    #[serde(default = "default_config_target")]
+    pub config_target: PathBuf,
+}
+
+fn default_config_target() -> PathBuf { PathBuf::from("/etc/wireguard/wg0.conf") }
+
+impl Default for WireGuardOptions {
+    fn default() -> Self { Self { config_target: default_config_target() } }
+}
+
+/// Runs wg-quick in a container, the proxy listening on `container_port` is
+/// provided by a sidecar process of the image.
+#[derive(Debug, Clone)]
+pub struct DockerWireGuardTunnel {
+    pub docker_tunnel: DockerTunnel,
+    pub config_file: PathBuf,
+    pub wireguard: WireGuardOptions,
+}
+
+impl DockerWireGuardTunnel {
+    fn container_spec(&self, context: &Context) -> Result<ContainerSpec, Error> {
+        let mounts = [DockerMount {
+            host_endpoint: self.config_file.clone(),
+            container_endpoint: self.wireguard.config_target.clone(),
+            read_only: true,
+        }];
+        self.docker_tunnel.check_mounts(context, &mounts)?;
+
+        let mut spec = self.docker_tunnel.container_spec(context, &mounts)?;
+        // wireguard-go falls back to the tun device without the kernel module
+        spec.request_tun();
+        // wg-quick marks its packets to route everything through the tunnel
+        let _unused = spec
+            .sysctls
+            .entry("net.ipv4.conf.all.src_valid_mark".to_owned())
+            .or_insert_with(|| "1".to_owned());
+        Ok(spec)
+    }
+}
+
+impl Tunnel for DockerWireGuardTunnel {
+    #[inline]
+    fn meta(&self) -> &TunnelMeta { self.docker_tunnel.meta() }
+
+    #[inline]
+    fn tunnel_type(&self) -> TunnelType { TunnelType::DockerWireGuard }
+
+    #[inline]
+    fn start(&self, context: &Context) -> Result<(), Error> {
+        self.docker_tunnel.start_with_spec(context, &self.container_spec(context)?)
+    }
+
+    #[inline]
+    fn stop(&self, context: &Context) -> Result<(), Error> { self.docker_tunnel.stop(context) }
+
+    #[inline]
+    fn pull(&self, context: &Context) -> Result<(), Error> { self.docker_tunnel.pull(context) }
+
+    #[inline]
+    fn build(&self, context: &Context) -> Result<(), Error> { self.docker_tunnel.build(context) }
+
+    #[inline]
+    fn is_running(&self, context: &Context) -> Result<bool, Error> {
+        self.docker_tunnel.is_running(context)
+    }
+
+    #[inline]
+    fn logs(&self, context: &Context, follow: bool, tail: Option<usize>) -> Result<(), Error> {
+        self.docker_tunnel.logs(context, follow, tail)
+    }
+
+    #[inline]
+    fn container(&self) -> Option<&DockerTunnel> { Some(&self.docker_tunnel) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tunnel::{test_util::TempDir, ContainerOptions, ContainerRuntime};
+
+    #[test]
+    fn test_run_args() {
+        let dir = TempDir::new("wireguard");
+        let config_file = dir.path().join("wg0.conf");
+        std::fs::write(&config_file, "[Interface]\n").unwrap();
+        let tunnel = DockerWireGuardTunnel {
+            docker_tunnel: DockerTunnel {
+                meta: TunnelMeta { name: "wg".to_owned(), description: None },
+                runtime: ContainerRuntime::Docker,
+                engine: None,
+                config_id: String::new(),
+                options: ContainerOptions::default(),
+                image_name: "wireguard-socks".to_owned(),
+                container_name: "wg".to_owned(),
+                container_port: 1080,
+                listen_host: "127.0.0.1".to_owned(),
+                listen_port: 1080,
+            },
+            config_file: config_file.clone(),
+            wireguard: WireGuardOptions::default(),
+        };
+        let context = Context::new("the-user", "/home/the-user", dir.path());
+
+        let args = tunnel.container_spec(&context).unwrap().to_run_args(None);
+        std::fs::remove_file(&config_file).unwrap();
+        assert!(args.contains(&"--cap-add=NET_ADMIN".to_owned()));
+        assert!(args.contains(&"--sysctl=net.ipv4.conf.all.src_valid_mark=1".to_owned()));
+        assert!(args.contains(&format!(
+            "type=bind,source={},destination=/etc/wireguard/wg0.conf,readonly=true",
+            config_file.display()
+        )));
+
+        assert!(matches!(tunnel.container_spec(&context), Err(Error::FileNotFound { .. })));
+    }
+}
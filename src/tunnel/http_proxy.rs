@@ -0,0 +1,583 @@
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Shutdown, TcpStream},
+    path::PathBuf,
+    thread,
+};
+
+use crate::{
+    context::Context,
+    error::Error,
+    tunnel::{log_file, process::BackgroundProcess, relay, Tunnel, TunnelMeta, TunnelType},
+};
+
+const MAX_HEADER_SIZE: u64 = 64 * 1024;
+
+/// Accepts HTTP `CONNECT` and plain HTTP proxy requests and forwards them
+/// through the SOCKS endpoint of another tunnel.
+#[derive(Clone, Debug)]
+pub struct HttpProxyTunnel {
+    pub meta: TunnelMeta,
+    pub listen_host: String,
+    pub listen_port: u16,
+    /// Name of the tunnel providing the SOCKS endpoint.
+    pub upstream: String,
+    pub socks_host: String,
+    pub socks_port: u16,
+    pub max_connections: usize,
+}
+
+impl HttpProxyTunnel {
+    pub const DEFAULT_MAX_CONNECTIONS: usize = 64;
+
+    #[inline]
+    pub fn pid_file(&self, context: &Context) -> PathBuf {
+        context.control_path_directory().join(format!("{}.pid", self.name()))
+    }
+
+    #[inline]
+    pub fn log_file(&self, context: &Context) -> PathBuf {
+        context.control_path_directory().join(format!("{}.log", self.name()))
+    }
+
+    #[inline]
+    pub fn control_socket(&self, context: &Context) -> PathBuf {
+        context.control_path_directory().join(format!("{}.proxy.socket", self.name()))
+    }
+
+    pub fn server(&self, context: &Context) -> HttpProxyServer {
+        // a SOCKS endpoint listening on all interfaces is reachable on loopback
+        let socks_host = match self.socks_host.as_str() {
+            "0.0.0.0" => "127.0.0.1",
+            "::" => "::1",
+            host => host,
+        };
+        HttpProxyServer {
+            listen_host: self.listen_host.clone(),
+            listen_port: self.listen_port,
+            socks_host: socks_host.to_owned(),
+            socks_port: self.socks_port,
+            max_connections: self.max_connections,
+            control_socket: self.control_socket(context),
+        }
+    }
+
+    fn process(&self, context: &Context) -> Result<BackgroundProcess, Error> {
+        relay::server_process(
+            self.server(context).to_args(),
+            self.pid_file(context),
+            self.log_file(context),
+        )
+    }
+}
+
+/// The proxy itself, run by the hidden `http-proxy` command.
+#[derive(Clone, Debug)]
+pub struct HttpProxyServer {
+    pub listen_host: String,
+    pub listen_port: u16,
+    pub socks_host: String,
+    pub socks_port: u16,
+    pub max_connections: usize,
+    /// Socket accepting the `status` and `stop` commands.
+    pub control_socket: PathBuf,
+}
+
+impl HttpProxyServer {
+    /// Returns the arguments of tunka running this proxy.
+    pub fn to_args(&self) -> Vec<String> {
+        vec![
+            "http-proxy".to_owned(),
+            "--listen-host".to_owned(),
+            self.listen_host.clone(),
+            "--listen-port".to_owned(),
+            self.listen_port.to_string(),
+            "--socks-host".to_owned(),
+            self.socks_host.clone(),
+            "--socks-port".to_owned(),
+            self.socks_port.to_string(),
+            "--max-connections".to_owned(),
+            self.max_connections.to_string(),
+            "--control-socket".to_owned(),
+            self.control_socket.to_string_lossy().into_owned(),
+        ]
+    }
+
+    /// Serves connections until the `stop` command is received.
+    pub fn run(&self) -> Result<(), Error> {
+        let listener = relay::bind(&self.listen_host, self.listen_port)?;
+        let socks = (self.socks_host.clone(), self.socks_port);
+        tracing::info!(
+            "Proxying {}:{} through SOCKS endpoint {}:{}, at most {} connections",
+            self.listen_host,
+            self.listen_port,
+            self.socks_host,
+            self.socks_port,
+            self.max_connections
+        );
+        relay::serve(
+            &listener,
+            &self.control_socket,
+            self.max_connections,
+            move |client, peer| {
+                if let Err(err) = proxy_connection(client, peer, (&socks.0, socks.1)) {
+                    tracing::warn!("Could not proxy connection from {peer}, error: {err}");
+                }
+            },
+        )?;
+        tracing::info!("Proxy stopped");
+        Ok(())
+    }
+}
+
+/// The head of an HTTP request, up to the empty line.
+#[derive(Debug, Eq, PartialEq)]
+struct RequestHead {
+    method: String,
+    target: String,
+    version: String,
+    headers: Vec<(String, String)>,
+}
+
+impl RequestHead {
+    fn read(reader: &mut impl BufRead) -> io::Result<Self> {
+        // limits the reads themselves, a line without an end would be buffered as a
+        // whole otherwise
+        let mut reader = reader.take(MAX_HEADER_SIZE);
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                if reader.limit() == 0 {
+                    return Err(invalid_request("request head is too large"));
+                }
+                break;
+            }
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.is_empty() {
+                // tolerate empty lines before the request line
+                if lines.is_empty() {
+                    continue;
+                }
+                break;
+            }
+            lines.push(line.to_owned());
+        }
+
+        let mut lines = lines.into_iter();
+        let request_line = lines.next().ok_or_else(|| invalid_request("empty request"))?;
+        let mut parts = request_line.split_whitespace().map(ToOwned::to_owned);
+        let (Some(method), Some(target), Some(version), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid_request("malformed request line"));
+        };
+        let headers = lines
+            .map(|line| {
+                line.split_once(':')
+                    .map(|(name, value)| (name.trim().to_owned(), value.trim().to_owned()))
+                    .ok_or_else(|| invalid_request("malformed header"))
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(Self { method, target, version, headers })
+    }
+
+    /// Returns where to connect to, and for plain HTTP the request to send
+    /// there in origin form.
+    fn destination(&self) -> io::Result<(String, u16, Option<String>)> {
+        if self.method.eq_ignore_ascii_case("CONNECT") {
+            let (host, port) = split_host_port(&self.target, None)?;
+            return Ok((host, port, None));
+        }
+
+        let rest = self
+            .target
+            .strip_prefix("http://")
+            .ok_or_else(|| invalid_request("only http:// URLs can be proxied without CONNECT"))?;
+        let (authority, path) = rest.find('/').map_or((rest, "/"), |i| rest.split_at(i));
+        let (host, port) = split_host_port(authority, Some(80))?;
+
+        let mut head = format!("{} {path} {}\r\n", self.method, self.version);
+        for (name, value) in &self.headers {
+            // every request gets a connection of its own
+            if !["connection", "proxy-connection", "proxy-authorization", "keep-alive"]
+                .iter()
+                .any(|hop| name.eq_ignore_ascii_case(hop))
+            {
+                let _unused = write!(head, "{name}: {value}\r\n");
+            }
+        }
+        head.push_str("Connection: close\r\n\r\n");
+        Ok((host, port, Some(head)))
+    }
+
+    /// Returns the length of the body following the head, a chunked body
+    /// would have to be parsed to find its end.
+    fn body_length(&self) -> io::Result<u64> {
+        let header = |name: &str| {
+            self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, v)| v)
+        };
+        if header("transfer-encoding").is_some_and(|value| !value.eq_ignore_ascii_case("identity"))
+        {
+            return Err(invalid_request("request bodies without a length are not supported"));
+        }
+        header("content-length")
+            .map_or(Ok(0), |value| value.parse().map_err(|_| invalid_request("invalid length")))
+    }
+}
+
+/// Reads the head of a response and returns it with the connection closed
+/// after the response, as the client connection serves only one request.
+fn read_response_head(reader: &mut impl BufRead) -> io::Result<String> {
+    let mut reader = reader.take(MAX_HEADER_SIZE);
+    let mut head = String::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid_request("incomplete response head"));
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            break;
+        }
+        let name = line.split(':').next().unwrap_or_default().trim();
+        if !["connection", "proxy-connection", "keep-alive"]
+            .iter()
+            .any(|hop| name.eq_ignore_ascii_case(hop))
+        {
+            let _unused = write!(head, "{line}\r\n");
+        }
+    }
+    head.push_str("Connection: close\r\nProxy-Connection: close\r\n\r\n");
+    Ok(head)
+}
+
+fn split_host_port(authority: &str, default_port: Option<u16>) -> io::Result<(String, u16)> {
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => {
+            (host, Some(port.parse().map_err(|_| invalid_request("invalid port"))?))
+        }
+        _ => (authority, default_port),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    match port {
+        Some(port) if !host.is_empty() => Ok((host.to_owned(), port)),
+        _ => Err(invalid_request("missing host or port")),
+    }
+}
+
+fn invalid_request(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+}
+
+fn proxy_connection(client: TcpStream, peer: &str, socks: (&str, u16)) -> io::Result<()> {
+    let mut reader = BufReader::new(client.try_clone()?);
+    let mut writer = client;
+    let head = match RequestHead::read(&mut reader) {
+        Ok(head) => head,
+        Err(err) => {
+            let _unused =
+                writer.write_all(b"HTTP/1.1 400 Bad Request\r\nConnection: close\r\n\r\n");
+            return Err(err);
+        }
+    };
+    let (host, port, request) = match head.destination() {
+        Ok(destination) => destination,
+        Err(err) => {
+            let _unused =
+                writer.write_all(b"HTTP/1.1 400 Bad Request\r\nConnection: close\r\n\r\n");
+            return Err(err);
+        }
+    };
+    // plain HTTP requests come with the length of their body
+    let request = match request.map(|request| Ok((request, head.body_length()?))).transpose() {
+        Ok(request) => request,
+        Err(err) => {
+            let _unused =
+                writer.write_all(b"HTTP/1.1 411 Length Required\r\nConnection: close\r\n\r\n");
+            return Err(err);
+        }
+    };
+
+    let mut upstream = match socks5_connect(socks, &host, port) {
+        Ok(upstream) => upstream,
+        Err(err) => {
+            let _unused =
+                writer.write_all(b"HTTP/1.1 502 Bad Gateway\r\nConnection: close\r\n\r\n");
+            return Err(err);
+        }
+    };
+    tracing::info!("{} {} from {peer} opened", head.method, head.target);
+
+    let (sent, received) = if let Some((request, body_length)) = request {
+        forward_request(reader, writer, upstream, &request, body_length)?
+    } else {
+        writer.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")?;
+        // the client may have sent more than the head already
+        upstream.write_all(reader.buffer())?;
+        let upstream_writer = upstream.try_clone()?;
+        let upload = thread::spawn(move || relay::copy(reader.into_inner(), upstream_writer));
+        let received = relay::copy(upstream, writer);
+        (upload.join().unwrap_or_default(), received)
+    };
+    tracing::info!(
+        "{} {} from {peer} closed, sent {sent} bytes, received {received} bytes",
+        head.method,
+        head.target
+    );
+    Ok(())
+}
+
+/// Sends a plain HTTP request with its body and passes the response back.
+/// Nothing after the body is read from the client, a follow-up request may
+/// be for another host.
+fn forward_request(
+    mut reader: BufReader<TcpStream>,
+    mut writer: TcpStream,
+    mut upstream: TcpStream,
+    request: &str,
+    body_length: u64,
+) -> io::Result<(u64, u64)> {
+    upstream.write_all(request.as_bytes())?;
+    let sent = io::copy(&mut (&mut reader).take(body_length), &mut upstream)?;
+
+    let mut upstream = BufReader::new(upstream);
+    let response_head = read_response_head(&mut upstream)?;
+    writer.write_all(response_head.as_bytes())?;
+    let received = io::copy(&mut upstream, &mut writer)?;
+    let _unused = writer.shutdown(Shutdown::Both);
+    Ok((sent, received))
+}
+
+/// Opens a connection to `host:port` through a SOCKS5 proxy, leaving name
+/// resolution to the proxy.
+fn socks5_connect(socks: (&str, u16), host: &str, port: u16) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect(socks)?;
+    stream.write_all(&[5, 1, 0])?;
+    let mut reply = [0; 2];
+    stream.read_exact(&mut reply)?;
+    if reply != [5, 0] {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "SOCKS proxy requires authentication",
+        ));
+    }
+
+    let host_len = u8::try_from(host.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "host name is too long"))?;
+    let mut request = vec![5, 1, 0, 3, host_len];
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request)?;
+
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply)?;
+    if reply[1] != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("SOCKS proxy could not connect to {host}:{port}, reply: {}", reply[1]),
+        ));
+    }
+    // the bound address is of no use here
+    let address_len = match reply[3] {
+        1 => 4,
+        4 => 16,
+        3 => {
+            let mut len = [0; 1];
+            stream.read_exact(&mut len)?;
+            usize::from(len[0])
+        }
+        _ => return Err(invalid_request("invalid SOCKS reply")),
+    };
+    let mut bound = vec![0; address_len + 2];
+    stream.read_exact(&mut bound)?;
+    Ok(stream)
+}
+
+impl Tunnel for HttpProxyTunnel {
+    #[inline]
+    fn meta(&self) -> &TunnelMeta { &self.meta }
+
+    #[inline]
+    fn tunnel_type(&self) -> TunnelType { TunnelType::HttpProxy }
+
+    #[inline]
+    fn start(&self, context: &Context) -> Result<(), Error> {
+        relay::start_server(self.name(), &self.process(context)?, &self.control_socket(context))
+    }
+
+    #[inline]
+    fn stop(&self, context: &Context) -> Result<(), Error> {
+        relay::stop_server(&self.process(context)?, &self.control_socket(context))
+    }
+
+    #[inline]
+    fn is_running(&self, context: &Context) -> Result<bool, Error> {
+        Ok(relay::is_server_running(&self.process(context)?, &self.control_socket(context)))
+    }
+
+    #[inline]
+    fn logs(&self, context: &Context, follow: bool, tail: Option<usize>) -> Result<(), Error> {
+        log_file::print(&self.log_file(context), follow, tail)
+    }
+
+    #[inline]
+    fn upstream(&self) -> Option<&str> { Some(&self.upstream) }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, time::Duration};
+
+    use super::*;
+
+    fn head(request: &str) -> RequestHead {
+        RequestHead::read(&mut BufReader::new(request.as_bytes())).unwrap()
+    }
+
+    #[test]
+    fn test_to_args() {
+        let tunnel = HttpProxyTunnel {
+            meta: TunnelMeta { name: "web".to_owned(), description: None },
+            listen_host: "127.0.0.1".to_owned(),
+            listen_port: 8118,
+            upstream: "bastion".to_owned(),
+            socks_host: "0.0.0.0".to_owned(),
+            socks_port: 1080,
+            max_connections: HttpProxyTunnel::DEFAULT_MAX_CONNECTIONS,
+        };
+        let context = Context::new("the-user", "/home/the-user", "/tmp/tunka");
+        assert_eq!(
+            tunnel.server(&context).to_args().join(" "),
+            "http-proxy --listen-host 127.0.0.1 --listen-port 8118 --socks-host 127.0.0.1 \
+             --socks-port 1080 --max-connections 64 --control-socket /tmp/tunka/web.proxy.socket"
+        );
+    }
+
+    #[test]
+    fn test_destination() {
+        let connect = head("CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n");
+        assert_eq!(connect.destination().unwrap(), ("example.com".to_owned(), 443, None));
+
+        let connect = head("CONNECT [2001:db8::1]:8443 HTTP/1.1\r\n\r\n");
+        assert_eq!(connect.destination().unwrap(), ("2001:db8::1".to_owned(), 8443, None));
+
+        let get = head(
+            "GET http://example.com/index.html?q=1 HTTP/1.1\r\nHost: \
+             example.com\r\nProxy-Connection: keep-alive\r\nAccept: */*\r\n\r\n",
+        );
+        assert_eq!(
+            get.destination().unwrap(),
+            (
+                "example.com".to_owned(),
+                80,
+                Some(
+                    "GET /index.html?q=1 HTTP/1.1\r\nHost: example.com\r\nAccept: \
+                     */*\r\nConnection: close\r\n\r\n"
+                        .to_owned()
+                )
+            )
+        );
+
+        assert!(head("GET https://example.com/ HTTP/1.1\r\n\r\n").destination().is_err());
+        assert!(RequestHead::read(&mut BufReader::new(&b"GET /\r\n\r\n"[..])).is_err());
+
+        let endless = vec![b'a'; 2 * 64 * 1024];
+        let err = RequestHead::read(&mut BufReader::new(&endless[..])).unwrap_err();
+        assert_eq!(err.to_string(), "request head is too large");
+    }
+
+    /// A SOCKS5 server connecting every request to `target`.
+    fn socks_server(target: u16) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let _unused = thread::spawn(move || {
+            for mut client in listener.incoming().flatten() {
+                let mut greeting = [0; 3];
+                client.read_exact(&mut greeting).unwrap();
+                client.write_all(&[5, 0]).unwrap();
+                let mut request = [0; 5];
+                client.read_exact(&mut request).unwrap();
+                let mut rest = vec![0; usize::from(request[4]) + 2];
+                client.read_exact(&mut rest).unwrap();
+                assert_eq!(&rest[..rest.len() - 2], b"example.com");
+                client.write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0]).unwrap();
+
+                let upstream = TcpStream::connect(("127.0.0.1", target)).unwrap();
+                let reader = client.try_clone().unwrap();
+                let writer = upstream.try_clone().unwrap();
+                let _unused = thread::spawn(move || relay::copy(reader, writer));
+                let _unused = thread::spawn(move || relay::copy(upstream, client));
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn test_proxy() {
+        let origin = TcpListener::bind("127.0.0.1:0").unwrap();
+        let origin_port = origin.local_addr().unwrap().port();
+        let _unused = thread::spawn(move || {
+            for mut stream in origin.incoming().flatten() {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let head = RequestHead::read(&mut reader);
+                if let Ok(head) = &head {
+                    let mut body = vec![0; usize::try_from(head.body_length().unwrap()).unwrap()];
+                    reader.read_exact(&mut body).unwrap();
+                }
+                let body = head.map_or_else(|_| "tunnel".to_owned(), |head| head.target);
+                let response =
+                    format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{body}", body.len());
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        let socks_port = socks_server(origin_port);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy = listener.local_addr().unwrap();
+        let _unused = thread::spawn(move || {
+            for client in listener.incoming().flatten() {
+                let _unused = thread::spawn(move || {
+                    proxy_connection(client, "test", ("127.0.0.1", socks_port)).unwrap();
+                });
+            }
+        });
+
+        let mut client = TcpStream::connect(proxy).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client
+            .write_all(b"GET http://example.com/hello HTTP/1.1\r\nHost: example.com\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        let _unused = client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(response.ends_with("/hello"), "{response}");
+
+        // the second request is neither sent to the origin of the first one nor
+        // answered
+        let mut client = TcpStream::connect(proxy).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client
+            .write_all(
+                b"POST http://example.com/first HTTP/1.1\r\nHost: example.com\r\nContent-Length: \
+                  4\r\n\r\nbodyGET http://other.example/second HTTP/1.1\r\nHost: \
+                  other.example\r\n\r\n",
+            )
+            .unwrap();
+        let mut response = String::new();
+        let _unused = client.read_to_string(&mut response).unwrap();
+        assert!(response.contains("\r\nConnection: close\r\n"), "{response}");
+        assert!(response.contains("\r\nProxy-Connection: close\r\n"), "{response}");
+        assert!(response.ends_with("/first"), "{response}");
+        assert!(!response.contains("/second"), "{response}");
+
+        let mut client = TcpStream::connect(proxy).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client.write_all(b"CONNECT example.com:443 HTTP/1.1\r\n\r\n").unwrap();
+        let mut reader = BufReader::new(client.try_clone().unwrap());
+        let mut status = String::new();
+        let _unused = reader.read_line(&mut status).unwrap();
+        assert_eq!(status, "HTTP/1.1 200 Connection established\r\n");
+    }
+}
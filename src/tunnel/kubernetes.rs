@@ -0,0 +1,186 @@
+use std::{path::PathBuf, process::Command, time::Duration};
+
+use crate::{
+    context::Context,
+    error::Error,
+    tunnel::{log_file, process::BackgroundProcess, Tunnel, TunnelMeta, TunnelType},
+};
+
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Forwards a local port to a pod, service or deployment with
+/// `kubectl port-forward`.
+#[derive(Clone, Debug)]
+pub struct KubernetesTunnel {
+    pub meta: TunnelMeta,
+    /// Path or name of the kubectl program.
+    pub kubectl: String,
+    pub kubeconfig: Option<PathBuf>,
+    pub kube_context: Option<String>,
+    pub namespace: Option<String>,
+    /// Resource to forward to, e.g. `svc/grafana` or `pod/postgres-0`.
+    pub resource: String,
+    pub listen_host: String,
+    pub listen_port: u16,
+    pub remote_port: u16,
+    pub extra_args: Vec<String>,
+}
+
+impl KubernetesTunnel {
+    #[inline]
+    pub fn pid_file(&self, context: &Context) -> PathBuf {
+        context.control_path_directory().join(format!("{}.pid", self.name()))
+    }
+
+    #[inline]
+    pub fn log_file(&self, context: &Context) -> PathBuf {
+        context.control_path_directory().join(format!("{}.log", self.name()))
+    }
+
+    pub fn port_forward_args(&self, context: &Context) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(kubeconfig) = &self.kubeconfig {
+            args.push("--kubeconfig".to_owned());
+            args.push(context.apply_path(kubeconfig).to_string_lossy().into_owned());
+        }
+        if let Some(kube_context) = &self.kube_context {
+            args.push("--context".to_owned());
+            args.push(kube_context.clone());
+        }
+        if let Some(namespace) = &self.namespace {
+            args.push("--namespace".to_owned());
+            args.push(namespace.clone());
+        }
+        args.extend(["port-forward".to_owned(), "--address".to_owned(), self.listen_host.clone()]);
+        args.extend(self.extra_args.iter().cloned());
+        args.push(self.resource.clone());
+        args.push(format!("{}:{}", self.listen_port, self.remote_port));
+        args
+    }
+
+    fn process(&self, context: &Context) -> BackgroundProcess {
+        BackgroundProcess {
+            program: self.kubectl.clone(),
+            args: self.port_forward_args(context),
+            pid_file: self.pid_file(context),
+            log_file: self.log_file(context),
+        }
+    }
+}
+
+impl Tunnel for KubernetesTunnel {
+    #[inline]
+    fn meta(&self) -> &TunnelMeta { &self.meta }
+
+    #[inline]
+    fn tunnel_type(&self) -> TunnelType { TunnelType::Kubernetes }
+
+    /// Starts kubectl in the background and waits until it reports the
+    /// forwarded port, kubectl fails quickly on unknown resources or contexts.
+    fn start(&self, context: &Context) -> Result<(), Error> {
+        let process = self.process(context);
+        if process.is_running() {
+            return Ok(());
+        }
+
+        let mut child = process.spawn(&mut Command::new(&self.kubectl))?;
+        process.wait_until_ready(self.name(), &mut child, READY_TIMEOUT, |log| {
+            log.contains("Forwarding from")
+        })
+    }
+
+    #[inline]
+    fn stop(&self, context: &Context) -> Result<(), Error> { self.process(context).terminate() }
+
+    #[inline]
+    fn is_running(&self, context: &Context) -> Result<bool, Error> {
+        Ok(self.process(context).is_running())
+    }
+
+    #[inline]
+    fn logs(&self, context: &Context, follow: bool, tail: Option<usize>) -> Result<(), Error> {
+        log_file::print(&self.log_file(context), follow, tail)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, thread, time::Instant};
+
+    use super::*;
+    use crate::tunnel::test_util::{write_script, TempDir};
+
+    fn context(dir: &TempDir) -> Context { Context::new("the-user", "/home/the-user", dir.path()) }
+
+    fn tunnel(kubectl: &Path) -> KubernetesTunnel {
+        KubernetesTunnel {
+            meta: TunnelMeta { name: "grafana".to_owned(), description: None },
+            kubectl: kubectl.to_string_lossy().into_owned(),
+            kubeconfig: Some(PathBuf::from("$HOME/.kube/config")),
+            kube_context: Some("prod".to_owned()),
+            namespace: Some("monitoring".to_owned()),
+            resource: "svc/grafana".to_owned(),
+            listen_host: "127.0.0.1".to_owned(),
+            listen_port: 3000,
+            remote_port: 80,
+            extra_args: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_port_forward_args() {
+        let dir = TempDir::new("kubernetes-args");
+        let context = context(&dir);
+        let kubectl = write_script(dir.path(), "kubectl", "exit 0");
+        assert_eq!(
+            tunnel(&kubectl).port_forward_args(&context).join(" "),
+            "--kubeconfig /home/the-user/.kube/config --context prod --namespace monitoring \
+             port-forward --address 127.0.0.1 svc/grafana 3000:80"
+        );
+    }
+
+    #[test]
+    fn test_supervision() {
+        let dir = TempDir::new("kubernetes-supervision");
+        let context = context(&dir);
+        let kubectl = write_script(
+            dir.path(),
+            "kubectl",
+            "echo 'Forwarding from 127.0.0.1:3000 -> 80'\nwhile :; do sleep 1; done",
+        );
+        let tunnel = tunnel(&kubectl);
+        assert!(!tunnel.is_running(&context).unwrap());
+
+        tunnel.start(&context).unwrap();
+        assert!(tunnel.is_running(&context).unwrap());
+        assert!(tunnel.pid_file(&context).exists());
+
+        tunnel.stop(&context).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while tunnel.is_running(&context).unwrap() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert!(!tunnel.is_running(&context).unwrap());
+        assert!(!tunnel.pid_file(&context).exists());
+    }
+
+    #[test]
+    fn test_start_failure() {
+        let dir = TempDir::new("kubernetes-failure");
+        let context = context(&dir);
+        let kubectl = write_script(
+            dir.path(),
+            "kubectl",
+            "echo 'error: services \"grafana\" not found' >&2\nexit 1",
+        );
+        let mut tunnel = tunnel(&kubectl);
+        tunnel.meta.name = "grafana-missing".to_owned();
+
+        let err = tunnel.start(&context).unwrap_err();
+        assert!(
+            matches!(&err, Error::ExternalCommand { code: 1, stderr, .. } if stderr.contains("not found")),
+            "{err}"
+        );
+        assert!(!tunnel.is_running(&context).unwrap());
+    }
+}
@@ -0,0 +1,71 @@
+use std::{
+    fs::File,
+    io::{Read, Seek},
+    path::Path,
+    thread,
+    time::Duration,
+};
+
+use snafu::ResultExt;
+
+use crate::error::{self, Error};
+
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Prints the last `tail` lines of a log file, or all of it, then keeps
+/// printing what is appended to it if `follow` is set.
+pub fn print(file_path: &Path, follow: bool, tail: Option<usize>) -> Result<(), Error> {
+    let read_context = || error::ReadLogFileSnafu { file_path: file_path.to_owned() };
+    let mut file = File::open(file_path).with_context(|_| read_context())?;
+    let mut content = Vec::new();
+    let _unused = file.read_to_end(&mut content).with_context(|_| read_context())?;
+    print!("{}", String::from_utf8_lossy(last_lines(&content, tail)));
+    if !follow {
+        return Ok(());
+    }
+
+    let mut position = file.stream_position().with_context(|_| read_context())?;
+    loop {
+        thread::sleep(FOLLOW_INTERVAL);
+        // the file is truncated whenever the tunnel is started again
+        if file.metadata().with_context(|_| read_context())?.len() < position {
+            file.rewind().with_context(|_| read_context())?;
+        }
+
+        let mut appended = Vec::new();
+        let _unused = file.read_to_end(&mut appended).with_context(|_| read_context())?;
+        print!("{}", String::from_utf8_lossy(&appended));
+        position = file.stream_position().with_context(|_| read_context())?;
+    }
+}
+
+fn last_lines(content: &[u8], tail: Option<usize>) -> &[u8] {
+    match tail {
+        None => content,
+        Some(0) => &[],
+        Some(tail) => {
+            let body = content.strip_suffix(b"\n").unwrap_or(content);
+            body.iter()
+                .enumerate()
+                .rev()
+                .filter(|(_, b)| **b == b'\n')
+                .nth(tail - 1)
+                .map_or(content, |(index, _)| &content[index + 1..])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_last_lines() {
+        let content = b"one\ntwo\nthree\n";
+        assert_eq!(last_lines(content, None), content);
+        assert_eq!(last_lines(content, Some(0)), b"");
+        assert_eq!(last_lines(content, Some(2)), b"two\nthree\n");
+        assert_eq!(last_lines(content, Some(5)), content);
+        assert_eq!(last_lines(b"one\ntwo", Some(1)), b"two");
+    }
+}
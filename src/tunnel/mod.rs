@@ -1,13 +1,45 @@
+mod command;
 mod docker;
+mod docker_engine;
+mod docker_openconnect;
 mod docker_openvpn;
+mod docker_wireguard;
+mod http_proxy;
+mod kubernetes;
+mod log_file;
+mod process;
+mod relay;
+mod secret;
 mod ssh;
+#[cfg(test)]
+mod test_util;
+mod tor;
 
-use std::{collections::BTreeMap, fmt};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
 
 use serde::{Deserialize, Serialize};
 use snafu::ResultExt;
 
-pub use self::{docker::DockerTunnel, docker_openvpn::DockerOpenVPNTunnel, ssh::SshTunnel};
+pub use self::{
+    command::{CommandTunnel, ReadinessProbe},
+    docker::{
+        ContainerBackend, ContainerDefaults, ContainerOptions, ContainerRuntime, ContainerTarget,
+        DockerTunnel, EnvironmentValue, LabelledContainer,
+    },
+    docker_engine::DockerEngine,
+    docker_openconnect::{DockerOpenConnectTunnel, OpenConnectOptions},
+    docker_openvpn::{DockerOpenVPNTunnel, OpenVPNOptions},
+    docker_wireguard::{DockerWireGuardTunnel, WireGuardOptions},
+    http_proxy::{HttpProxyServer, HttpProxyTunnel},
+    kubernetes::KubernetesTunnel,
+    relay::{RelayServer, RelayTunnel},
+    secret::SecretSource,
+    ssh::{SshJumpHost, SshLocalForward, SshRemoteForward, SshTunnel},
+    tor::TorTunnel,
+};
 use crate::{context::Context, error, error::Error};
 
 #[derive(Debug, Clone, Deserialize, Eq, Hash, PartialEq, Serialize)]
@@ -21,6 +53,13 @@ pub enum TunnelType {
     Ssh,
     Docker,
     DockerOpenVPN,
+    DockerWireGuard,
+    DockerOpenConnect,
+    Tor,
+    Kubernetes,
+    Command,
+    Relay,
+    HttpProxy,
 }
 
 impl fmt::Display for TunnelType {
@@ -29,6 +68,13 @@ impl fmt::Display for TunnelType {
             Self::Ssh => write!(f, "SSH tunnel"),
             Self::Docker => write!(f, "Docker Tunnel"),
             Self::DockerOpenVPN => write!(f, "Docker OpenVPN Tunnel"),
+            Self::DockerWireGuard => write!(f, "Docker WireGuard Tunnel"),
+            Self::DockerOpenConnect => write!(f, "Docker OpenConnect Tunnel"),
+            Self::Tor => write!(f, "Tor Tunnel"),
+            Self::Kubernetes => write!(f, "Kubernetes Tunnel"),
+            Self::Command => write!(f, "Command Tunnel"),
+            Self::Relay => write!(f, "Relay Tunnel"),
+            Self::HttpProxy => write!(f, "HTTP Proxy Tunnel"),
         }
     }
 }
@@ -50,10 +96,40 @@ pub trait Tunnel {
     }
 
     fn is_running(&self, context: &Context) -> Result<bool, Error>;
+
+    /// Checks whether there is anything to stop, which includes a tunnel that
+    /// is started but not ready yet.
+    fn is_alive(&self, context: &Context) -> Result<bool, Error> { self.is_running(context) }
+
+    /// Removes what a tunnel leaves behind when it stops, also when it stopped
+    /// on its own, e.g. the credentials mounted into its container.
+    fn clean_up(&self, _context: &Context) {}
+
+    fn pull(&self, _context: &Context) -> Result<(), Error> {
+        Err(Error::UnsupportedOperation { tunnel: self.name().to_owned(), operation: "pull" })
+    }
+
+    fn build(&self, _context: &Context) -> Result<(), Error> {
+        Err(Error::UnsupportedOperation { tunnel: self.name().to_owned(), operation: "build" })
+    }
+
+    fn logs(&self, _context: &Context, _follow: bool, _tail: Option<usize>) -> Result<(), Error> {
+        Err(Error::UnsupportedOperation { tunnel: self.name().to_owned(), operation: "logs" })
+    }
+
+    /// Returns the container of container based tunnels.
+    fn container(&self) -> Option<&DockerTunnel> { None }
+
+    /// Returns the address of the SOCKS proxy provided by the tunnel.
+    fn socks_endpoint(&self) -> Option<(&str, u16)> { None }
+
+    /// Returns the name of the tunnel this tunnel depends on.
+    fn upstream(&self) -> Option<&str> { None }
 }
 
 pub struct TunnelManager {
     pub tunnels: BTreeMap<String, Box<dyn Tunnel>>,
+    pub container_defaults: ContainerDefaults,
 }
 
 impl TunnelManager {
@@ -74,10 +150,17 @@ impl TunnelManager {
         let tunnel = self
             .tunnels
             .get(tunnel_name)
-            .ok_or(Error::TunnelNotFound { tunnel: tunnel_name.to_owned() })?;
+            .ok_or_else(|| Error::TunnelNotFound { tunnel: tunnel_name.to_owned() })?;
+        if let Some(upstream) = tunnel.upstream() {
+            if !self.is_running(context, upstream)? {
+                self.start(context, upstream)?;
+            }
+        }
         println!("Start {} {tunnel_name}", tunnel.tunnel_type());
 
-        tunnel.start(context)?;
+        tunnel
+            .start(context)
+            .with_context(|_| error::StartTunnelSnafu { tunnel: tunnel_name.to_owned() })?;
         let _ = self.log_running_status(context, tunnel_name)?;
         Ok(())
     }
@@ -87,12 +170,13 @@ impl TunnelManager {
         let tunnel = self
             .tunnels
             .get(tunnel_name)
-            .ok_or(Error::TunnelNotFound { tunnel: tunnel_name.to_owned() })?;
+            .ok_or_else(|| Error::TunnelNotFound { tunnel: tunnel_name.to_owned() })?;
 
-        if tunnel.is_running(context)? {
+        if tunnel.is_alive(context)? {
             tracing::info!("Stop {} {tunnel_name}", tunnel.tunnel_type());
             tunnel.stop(context)?;
         }
+        tunnel.clean_up(context);
 
         let _ = self.log_running_status(context, tunnel_name)?;
         Ok(())
@@ -102,10 +186,84 @@ impl TunnelManager {
     pub fn restart(&self, context: &Context, tunnel_name: &str) -> Result<(), Error> {
         self.tunnels
             .get(tunnel_name)
-            .ok_or(Error::TunnelNotFound { tunnel: tunnel_name.to_owned() })?
+            .ok_or_else(|| Error::TunnelNotFound { tunnel: tunnel_name.to_owned() })?
             .restart(context)
     }
 
+    #[inline]
+    pub fn pull(&self, context: &Context, tunnel_name: &str) -> Result<(), Error> {
+        let tunnel = self
+            .tunnels
+            .get(tunnel_name)
+            .ok_or_else(|| Error::TunnelNotFound { tunnel: tunnel_name.to_owned() })?;
+        println!("Pull image of {} {tunnel_name}", tunnel.tunnel_type());
+        tunnel.pull(context)
+    }
+
+    #[inline]
+    pub fn build(&self, context: &Context, tunnel_name: &str) -> Result<(), Error> {
+        let tunnel = self
+            .tunnels
+            .get(tunnel_name)
+            .ok_or_else(|| Error::TunnelNotFound { tunnel: tunnel_name.to_owned() })?;
+        println!("Build image of {} {tunnel_name}", tunnel.tunnel_type());
+        tunnel.build(context)
+    }
+
+    #[inline]
+    pub fn logs(
+        &self,
+        context: &Context,
+        tunnel_name: &str,
+        follow: bool,
+        tail: Option<usize>,
+    ) -> Result<(), Error> {
+        self.tunnels
+            .get(tunnel_name)
+            .ok_or_else(|| Error::TunnelNotFound { tunnel: tunnel_name.to_owned() })?
+            .logs(context, follow, tail)
+    }
+
+    /// Lists the containers started from the current configuration file by
+    /// tunnels that have been renamed or removed since.
+    pub fn orphans(&self) -> Result<Vec<(ContainerRuntime, LabelledContainer)>, Error> {
+        let defaults = &self.container_defaults;
+        let containers = self.tunnels.values().filter_map(|t| t.container()).collect::<Vec<_>>();
+        let runtimes = std::iter::once(defaults.runtime)
+            .chain(containers.iter().map(|container| container.runtime))
+            .collect::<BTreeSet<_>>();
+
+        let mut orphans: Vec<(ContainerRuntime, LabelledContainer)> = Vec::new();
+        for runtime in runtimes {
+            for container in docker::labelled_containers(runtime, defaults.engine.as_ref())? {
+                // the same engine may be reached through several runtimes
+                if container.is_orphan(&defaults.config_id, &containers)
+                    && !orphans.iter().any(|(_, orphan)| orphan.name == container.name)
+                {
+                    orphans.push((runtime, container));
+                }
+            }
+        }
+        Ok(orphans)
+    }
+
+    #[inline]
+    pub fn remove_orphan(
+        &self,
+        context: &Context,
+        runtime: ContainerRuntime,
+        container: &LabelledContainer,
+    ) -> Result<(), Error> {
+        println!("Remove container {} of tunnel {}", container.name, container.tunnel);
+        docker::remove_container(
+            runtime,
+            self.container_defaults.engine.as_ref(),
+            &container.name,
+        )?;
+        secret::SecretFile::remove(context, &secret::credentials_file_name(&container.tunnel));
+        Ok(())
+    }
+
     #[inline]
     pub fn log_running_status(&self, context: &Context, tunnel_name: &str) -> Result<bool, Error> {
         if self.is_running(context, tunnel_name)? {
@@ -121,7 +279,7 @@ impl TunnelManager {
     pub fn is_running(&self, context: &Context, tunnel_name: &str) -> Result<bool, Error> {
         self.tunnels
             .get(tunnel_name)
-            .ok_or(Error::TunnelNotFound { tunnel: tunnel_name.to_owned() })?
+            .ok_or_else(|| Error::TunnelNotFound { tunnel: tunnel_name.to_owned() })?
             .is_running(context)
     }
 
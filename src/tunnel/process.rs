@@ -0,0 +1,180 @@
+use std::{
+    fs::File,
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
+    process::{Child, Command, Output, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use snafu::ResultExt;
+
+use crate::{
+    error::{self, Error},
+    tunnel::docker::convert_output,
+};
+
+const READY_INTERVAL: Duration = Duration::from_millis(200);
+
+const TERMINATE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A long running process started in the background, tracked through a
+/// pidfile holding its pid and start time and writing its output to a log
+/// file.
+#[derive(Clone, Debug)]
+pub struct BackgroundProcess {
+    pub program: String,
+    pub args: Vec<String>,
+    pub pid_file: PathBuf,
+    pub log_file: PathBuf,
+}
+
+impl BackgroundProcess {
+    /// Spawns the process in its own process group, so it outlives tunka and
+    /// does not receive the Ctrl-C typed into the terminal.
+    pub fn spawn(&self, command: &mut Command) -> Result<Child, Error> {
+        let file_path = &self.log_file;
+        let log_file = File::create(file_path)
+            .with_context(|_| error::CreateLogFileSnafu { file_path: file_path.clone() })?;
+        let stderr = log_file
+            .try_clone()
+            .with_context(|_| error::CreateLogFileSnafu { file_path: file_path.clone() })?;
+
+        let child = command
+            .args(&self.args)
+            .stdin(Stdio::null())
+            .stdout(log_file)
+            .stderr(stderr)
+            .process_group(0)
+            .spawn()
+            .with_context(|_| error::SpawnProcessSnafu { program: self.program.clone() })?;
+        let start_time = start_time(child.id()).unwrap_or_default();
+        std::fs::write(&self.pid_file, format!("{} {start_time}\n", child.id()))
+            .with_context(|_| error::WritePidFileSnafu { file_path: self.pid_file.clone() })?;
+        Ok(child)
+    }
+
+    /// Waits until `ready` accepts the log of the process, failing if the
+    /// process exits or `timeout` elapses before.
+    pub fn wait_until_ready(
+        &self,
+        tunnel: &str,
+        child: &mut Child,
+        timeout: Duration,
+        ready: impl Fn(&str) -> bool,
+    ) -> Result<(), Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let log = std::fs::read_to_string(&self.log_file).unwrap_or_default();
+            if ready(&log) {
+                return Ok(());
+            }
+            if let Some(status) = child.try_wait().context(error::WaitForProcessSnafu)? {
+                let _unused = std::fs::remove_file(&self.pid_file);
+                let output = Output { status, stdout: Vec::new(), stderr: log.into_bytes() };
+                convert_output(&self.program, &self.args, &output)?;
+                return Err(Error::ProcessNotReady { tunnel: tunnel.to_owned() });
+            }
+            if Instant::now() >= deadline {
+                let _unused = self.terminate();
+                return Err(Error::ProcessNotReady { tunnel: tunnel.to_owned() });
+            }
+            thread::sleep(READY_INTERVAL);
+        }
+    }
+
+    /// Returns the pid recorded in the pidfile if that process is still alive
+    /// and started at the recorded time, which guards against reused pids.
+    pub fn running_pid(&self) -> Option<u32> {
+        let pid_file = std::fs::read_to_string(&self.pid_file).ok()?;
+        let (pid, recorded) = pid_file.trim().split_once(' ')?;
+        let pid = pid.parse().ok()?;
+        (start_time(pid)? == recorded.parse::<u64>().ok()?).then_some(pid)
+    }
+
+    #[inline]
+    pub fn is_running(&self) -> bool { self.running_pid().is_some() }
+
+    /// Sends SIGTERM to the process group of the process, if it is running,
+    /// waits for the process to exit, killing it if it does not in time, and
+    /// removes the pidfile.
+    pub fn terminate(&self) -> Result<(), Error> {
+        if let Some(pid) = self.running_pid() {
+            kill_group("-TERM", pid)?;
+            let deadline = Instant::now() + TERMINATE_TIMEOUT;
+            while self.is_running() {
+                if Instant::now() >= deadline {
+                    tracing::warn!("Process {pid} of {} did not exit, kill it", self.program);
+                    kill_group("-KILL", pid)?;
+                    break;
+                }
+                thread::sleep(READY_INTERVAL);
+            }
+        }
+        let _unused = std::fs::remove_file(&self.pid_file);
+        Ok(())
+    }
+}
+
+/// Sends `signal` to the process group led by `pid`, see
+/// [`BackgroundProcess::spawn`].
+fn kill_group(signal: &str, pid: u32) -> Result<(), Error> {
+    let args = vec![signal.to_owned(), "--".to_owned(), format!("-{pid}")];
+    let output = Command::new("kill")
+        .args(&args)
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|_| error::SpawnProcessSnafu { program: "kill" })?;
+    convert_output("kill", &args, &output)
+}
+
+/// Returns the start time of a process in clock ticks since boot, from field
+/// 22 of `/proc/<pid>/stat`, or `None` if it has exited, zombies included.
+fn start_time(pid: u32) -> Option<u64> {
+    let stat = std::fs::read_to_string(Path::new("/proc").join(format!("{pid}/stat"))).ok()?;
+    // the command name in field 2 may contain spaces and parentheses
+    let (_, fields) = stat.rsplit_once(')')?;
+    let mut fields = fields.split_whitespace();
+    if matches!(fields.next()?, "Z" | "X") {
+        return None;
+    }
+    fields.nth(18)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tunnel::test_util::{write_script, TempDir};
+
+    #[test]
+    fn test_shebang_script() {
+        let dir = TempDir::new("process");
+        // runs as `/bin/sh <script>`, so its command line does not start with it
+        let script = write_script(dir.path(), "forward", "echo Forwarding\nsleep 30");
+        let process = BackgroundProcess {
+            program: script.to_string_lossy().into_owned(),
+            args: Vec::new(),
+            pid_file: dir.path().join("forward.pid"),
+            log_file: dir.path().join("forward.log"),
+        };
+
+        let mut child = process.spawn(&mut Command::new(&script)).unwrap();
+        process
+            .wait_until_ready("forward", &mut child, Duration::from_secs(5), |log| {
+                log.contains("Forwarding")
+            })
+            .unwrap();
+        let pid = process.running_pid().unwrap();
+        assert_eq!(pid, child.id());
+
+        // a reused pid starts at another time
+        std::fs::write(&process.pid_file, format!("{pid} 1\n")).unwrap();
+        assert!(!process.is_running());
+        std::fs::write(&process.pid_file, format!("{pid} {}\n", start_time(pid).unwrap())).unwrap();
+
+        process.terminate().unwrap();
+        assert!(!process.is_running());
+        assert!(!process.pid_file.exists());
+        let _unused = child.wait();
+    }
+}
@@ -0,0 +1,438 @@
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use snafu::ResultExt;
+
+use crate::{
+    context::Context,
+    error::{self, Error},
+    tunnel::{log_file, process::BackgroundProcess, Tunnel, TunnelMeta, TunnelType},
+};
+
+const READY_TIMEOUT: Duration = Duration::from_secs(10);
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+const STOP_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Forwards TCP connections to a target address from a background tunka
+/// process, without ssh or a container.
+#[derive(Clone, Debug)]
+pub struct RelayTunnel {
+    pub meta: TunnelMeta,
+    pub listen_host: String,
+    pub listen_port: u16,
+    pub target_host: String,
+    pub target_port: u16,
+    pub max_connections: usize,
+}
+
+impl RelayTunnel {
+    pub const DEFAULT_MAX_CONNECTIONS: usize = 64;
+
+    #[inline]
+    pub fn pid_file(&self, context: &Context) -> PathBuf {
+        context.control_path_directory().join(format!("{}.pid", self.name()))
+    }
+
+    #[inline]
+    pub fn log_file(&self, context: &Context) -> PathBuf {
+        context.control_path_directory().join(format!("{}.log", self.name()))
+    }
+
+    #[inline]
+    pub fn control_socket(&self, context: &Context) -> PathBuf {
+        context.control_path_directory().join(format!("{}.relay.socket", self.name()))
+    }
+
+    pub fn server(&self, context: &Context) -> RelayServer {
+        RelayServer {
+            listen_host: self.listen_host.clone(),
+            listen_port: self.listen_port,
+            target_host: self.target_host.clone(),
+            target_port: self.target_port,
+            max_connections: self.max_connections,
+            control_socket: self.control_socket(context),
+        }
+    }
+
+    fn process(&self, context: &Context) -> Result<BackgroundProcess, Error> {
+        server_process(
+            self.server(context).to_args(),
+            self.pid_file(context),
+            self.log_file(context),
+        )
+    }
+}
+
+/// The relay itself, run by the hidden `relay` command.
+#[derive(Clone, Debug)]
+pub struct RelayServer {
+    pub listen_host: String,
+    pub listen_port: u16,
+    pub target_host: String,
+    pub target_port: u16,
+    pub max_connections: usize,
+    /// Socket accepting the `status` and `stop` commands.
+    pub control_socket: PathBuf,
+}
+
+impl RelayServer {
+    /// Returns the arguments of tunka running this relay.
+    pub fn to_args(&self) -> Vec<String> {
+        vec![
+            "relay".to_owned(),
+            "--listen-host".to_owned(),
+            self.listen_host.clone(),
+            "--listen-port".to_owned(),
+            self.listen_port.to_string(),
+            "--target-host".to_owned(),
+            self.target_host.clone(),
+            "--target-port".to_owned(),
+            self.target_port.to_string(),
+            "--max-connections".to_owned(),
+            self.max_connections.to_string(),
+            "--control-socket".to_owned(),
+            self.control_socket.to_string_lossy().into_owned(),
+        ]
+    }
+
+    /// Serves connections until the `stop` command is received.
+    pub fn run(&self) -> Result<(), Error> {
+        let listener = bind(&self.listen_host, self.listen_port)?;
+        let target = format!("{}:{}", self.target_host, self.target_port);
+        tracing::info!(
+            "Relaying {}:{} to {target}, at most {} connections",
+            self.listen_host,
+            self.listen_port,
+            self.max_connections
+        );
+        serve(&listener, &self.control_socket, self.max_connections, move |client, peer| {
+            relay_connection(client, peer, &target);
+        })?;
+        tracing::info!("Relay stopped");
+        Ok(())
+    }
+}
+
+pub fn bind(host: &str, port: u16) -> Result<TcpListener, Error> {
+    TcpListener::bind((host, port))
+        .with_context(|_| error::BindSocketSnafu { address: format!("{host}:{port}") })
+}
+
+/// Hands every connection accepted by `listener` to `handler` on a thread of
+/// its own, until the `stop` command is received on `control_socket`.
+pub fn serve<F>(
+    listener: &TcpListener,
+    control_socket: &Path,
+    max_connections: usize,
+    handler: F,
+) -> Result<(), Error>
+where
+    F: Fn(TcpStream, &str) + Clone + Send + 'static,
+{
+    let local_addr = listener.local_addr().context(error::RelayIoSnafu)?;
+    let _unused = std::fs::remove_file(control_socket);
+    let control = UnixListener::bind(control_socket)
+        .with_context(|_| error::BindSocketSnafu { address: control_socket.to_string_lossy() })?;
+
+    let active = Arc::new(AtomicUsize::new(0));
+    let stopping = Arc::new(AtomicBool::new(false));
+    {
+        let (active, stopping) = (Arc::clone(&active), Arc::clone(&stopping));
+        let _unused = thread::spawn(move || {
+            for stream in control.incoming().flatten() {
+                let stop = serve_control(stream, &active, max_connections);
+                if stop {
+                    stopping.store(true, Ordering::SeqCst);
+                    // wakes up the accept loop below
+                    let _unused = TcpStream::connect(local_addr);
+                    break;
+                }
+            }
+        });
+    }
+
+    for stream in listener.incoming() {
+        if stopping.load(Ordering::SeqCst) {
+            break;
+        }
+        let client = match stream {
+            Ok(client) => client,
+            Err(err) => {
+                tracing::warn!("Could not accept connection, error: {err}");
+                continue;
+            }
+        };
+        let peer = client.peer_addr().map_or_else(|_| "unknown".to_owned(), |a| a.to_string());
+        if active.load(Ordering::SeqCst) >= max_connections {
+            tracing::warn!(
+                "Rejected connection from {peer}, limit of {max_connections} connections reached"
+            );
+            continue;
+        }
+
+        let _unused = active.fetch_add(1, Ordering::SeqCst);
+        let active = Arc::clone(&active);
+        let handler = handler.clone();
+        let _unused = thread::spawn(move || {
+            handler(client, &peer);
+            let _unused = active.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+
+    let _unused = std::fs::remove_file(control_socket);
+    Ok(())
+}
+
+/// Answers one control command, returns whether the relay has to stop.
+fn serve_control(stream: UnixStream, active: &AtomicUsize, max_connections: usize) -> bool {
+    let mut command = String::new();
+    let Ok(mut writer) = stream.try_clone() else {
+        return false;
+    };
+    if BufReader::new(stream).read_line(&mut command).is_err() {
+        return false;
+    }
+    match command.trim() {
+        "status" => {
+            let active = active.load(Ordering::SeqCst);
+            let _unused = writeln!(writer, "connections {active}/{max_connections}");
+            false
+        }
+        "stop" => {
+            let _unused = writeln!(writer, "stopping");
+            true
+        }
+        command => {
+            let _unused = writeln!(writer, "unknown command {command}");
+            false
+        }
+    }
+}
+
+fn relay_connection(client: TcpStream, peer: &str, target: &str) {
+    let upstream = target.to_socket_addrs().and_then(|mut addrs| {
+        addrs.find_map(|addr| TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT).ok()).ok_or_else(
+            || {
+                io::Error::new(
+                    io::ErrorKind::ConnectionRefused,
+                    "no address accepted the connection",
+                )
+            },
+        )
+    });
+    let upstream = match upstream {
+        Ok(upstream) => upstream,
+        Err(err) => {
+            tracing::warn!("Could not connect {peer} to {target}, error: {err}");
+            return;
+        }
+    };
+    tracing::info!("Connection from {peer} to {target} opened");
+
+    let (sent, received) = match (client.try_clone(), upstream.try_clone()) {
+        (Ok(client_reader), Ok(upstream_writer)) => {
+            let upload = thread::spawn(move || copy(client_reader, upstream_writer));
+            let received = copy(upstream, client);
+            (upload.join().unwrap_or_default(), received)
+        }
+        (Err(err), _) | (_, Err(err)) => {
+            tracing::warn!("Could not relay connection from {peer}, error: {err}");
+            return;
+        }
+    };
+    tracing::info!("Connection from {peer} closed, sent {sent} bytes, received {received} bytes");
+}
+
+/// Copies until EOF, then passes the EOF on.
+pub fn copy(mut reader: TcpStream, mut writer: TcpStream) -> u64 {
+    let bytes = io::copy(&mut reader, &mut writer).unwrap_or_default();
+    let _unused = writer.shutdown(Shutdown::Write);
+    bytes
+}
+
+/// Returns the background process of tunka running `args`.
+pub fn server_process(
+    args: Vec<String>,
+    pid_file: PathBuf,
+    log_file: PathBuf,
+) -> Result<BackgroundProcess, Error> {
+    let program = std::env::current_exe().context(error::CurrentExecutableSnafu)?;
+    Ok(BackgroundProcess {
+        program: program.to_string_lossy().into_owned(),
+        args,
+        pid_file,
+        log_file,
+    })
+}
+
+/// Starts a server in the background and waits until it answers on
+/// `control_socket`, which it binds after its listener.
+pub fn start_server(
+    tunnel: &str,
+    process: &BackgroundProcess,
+    control_socket: &Path,
+) -> Result<(), Error> {
+    if process.is_running() {
+        return Ok(());
+    }
+
+    let mut child = process.spawn(&mut Command::new(&process.program))?;
+    process.wait_until_ready(tunnel, &mut child, READY_TIMEOUT, |_log| {
+        control(control_socket, "status").is_ok()
+    })
+}
+
+/// Stops a server through its control socket and waits until it has exited
+/// and released its listener, signalling it if the socket does not answer
+/// or it does not exit in time.
+pub fn stop_server(process: &BackgroundProcess, control_socket: &Path) -> Result<(), Error> {
+    if control(control_socket, "stop").is_ok() {
+        let deadline = Instant::now() + STOP_TIMEOUT;
+        while process.is_running() && Instant::now() < deadline {
+            thread::sleep(STOP_INTERVAL);
+        }
+    }
+    // also removes the pidfile
+    process.terminate()
+}
+
+#[inline]
+pub fn is_server_running(process: &BackgroundProcess, control_socket: &Path) -> bool {
+    process.is_running() && control(control_socket, "status").is_ok()
+}
+
+/// Sends a command to the control socket of a relay and returns its answer.
+pub fn control(socket: &Path, command: &str) -> Result<String, Error> {
+    let mut stream = UnixStream::connect(socket).context(error::RelayIoSnafu)?;
+    stream.write_all(format!("{command}\n").as_bytes()).context(error::RelayIoSnafu)?;
+    let mut answer = String::new();
+    let _unused = BufReader::new(stream).read_line(&mut answer).context(error::RelayIoSnafu)?;
+    Ok(answer.trim_end().to_owned())
+}
+
+impl Tunnel for RelayTunnel {
+    #[inline]
+    fn meta(&self) -> &TunnelMeta { &self.meta }
+
+    #[inline]
+    fn tunnel_type(&self) -> TunnelType { TunnelType::Relay }
+
+    #[inline]
+    fn start(&self, context: &Context) -> Result<(), Error> {
+        start_server(self.name(), &self.process(context)?, &self.control_socket(context))
+    }
+
+    #[inline]
+    fn stop(&self, context: &Context) -> Result<(), Error> {
+        stop_server(&self.process(context)?, &self.control_socket(context))
+    }
+
+    #[inline]
+    fn is_running(&self, context: &Context) -> Result<bool, Error> {
+        Ok(is_server_running(&self.process(context)?, &self.control_socket(context)))
+    }
+
+    #[inline]
+    fn logs(&self, context: &Context, follow: bool, tail: Option<usize>) -> Result<(), Error> {
+        log_file::print(&self.log_file(context), follow, tail)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, time::Instant};
+
+    use super::*;
+
+    fn free_port() -> u16 { TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port() }
+
+    fn echo_server() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let _unused = thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let _unused = thread::spawn(move || {
+                    let mut reader = stream.try_clone().unwrap();
+                    let _unused = io::copy(&mut reader, &mut stream);
+                });
+            }
+        });
+        port
+    }
+
+    fn wait_for(socket: &Path) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while control(socket, "status").is_err() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    #[test]
+    fn test_to_args() {
+        let tunnel = RelayTunnel {
+            meta: TunnelMeta { name: "postgres".to_owned(), description: None },
+            listen_host: "127.0.0.1".to_owned(),
+            listen_port: 15432,
+            target_host: "10.0.0.5".to_owned(),
+            target_port: 5432,
+            max_connections: RelayTunnel::DEFAULT_MAX_CONNECTIONS,
+        };
+        let context = Context::new("the-user", "/home/the-user", "/tmp/tunka");
+        assert_eq!(
+            tunnel.server(&context).to_args().join(" "),
+            "relay --listen-host 127.0.0.1 --listen-port 15432 --target-host 10.0.0.5 \
+             --target-port 5432 --max-connections 64 --control-socket \
+             /tmp/tunka/postgres.relay.socket"
+        );
+    }
+
+    #[test]
+    fn test_relay() {
+        let dir = std::env::temp_dir().join(format!("tunka-relay-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let server = RelayServer {
+            listen_host: "127.0.0.1".to_owned(),
+            listen_port: free_port(),
+            target_host: "127.0.0.1".to_owned(),
+            target_port: echo_server(),
+            max_connections: 1,
+            control_socket: dir.join("relay.socket"),
+        };
+        let handle = {
+            let server = server.clone();
+            thread::spawn(move || server.run())
+        };
+        wait_for(&server.control_socket);
+
+        let mut first = TcpStream::connect(("127.0.0.1", server.listen_port)).unwrap();
+        first.write_all(b"ping").unwrap();
+        let mut buf = [0; 4];
+        first.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+        assert_eq!(control(&server.control_socket, "status").unwrap(), "connections 1/1");
+
+        // over the limit, closed right away
+        let mut second = TcpStream::connect(("127.0.0.1", server.listen_port)).unwrap();
+        second.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        assert!(matches!(second.read(&mut buf), Ok(0) | Err(_)));
+
+        assert_eq!(control(&server.control_socket, "stop").unwrap(), "stopping");
+        handle.join().unwrap().unwrap();
+        assert!(!server.control_socket.exists());
+    }
+}
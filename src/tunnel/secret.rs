@@ -0,0 +1,249 @@
+use std::{
+    collections::BTreeMap,
+    fs::OpenOptions,
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+
+use crate::{
+    context::Context,
+    error::{self, Error},
+    tunnel::docker::convert_output,
+};
+
+/// Where a secret, like the credentials of a VPN or the passphrase of a key,
+/// is read from.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretSource {
+    /// Output of a shell command, e.g. `pass show vpn/work`.
+    Command(String),
+    /// Value of an environment variable.
+    Env(String),
+    /// Item of the freedesktop Secret Service, looked up by its attributes
+    /// with `secret-tool`.
+    SecretService(BTreeMap<String, String>),
+}
+
+impl SecretSource {
+    pub fn resolve(&self, context: &Context) -> Result<String, Error> {
+        match self {
+            Self::Command(command) => run("sh", &["-c".to_owned(), context.apply(command)]),
+            Self::Env(name) => std::env::var(name)
+                .map_err(|_| Error::SecretEnvironmentVariableNotFound { name: name.clone() }),
+            Self::SecretService(attributes) => lookup_secret_service("secret-tool", attributes),
+        }
+    }
+}
+
+fn lookup_secret_service(
+    program: &str,
+    attributes: &BTreeMap<String, String>,
+) -> Result<String, Error> {
+    let args = std::iter::once("lookup".to_owned())
+        .chain(attributes.iter().flat_map(|(key, value)| [key.clone(), value.clone()]))
+        .collect::<Vec<_>>();
+    run(program, &args)
+}
+
+fn run(program: &str, args: &[String]) -> Result<String, Error> {
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|_| error::SpawnSecretCommandSnafu { program: program.to_owned() })?
+        .wait_with_output()
+        .with_context(|_| error::SpawnSecretCommandSnafu { program: program.to_owned() })?;
+    convert_output(program, args, &output)?;
+
+    let secret = String::from_utf8_lossy(&output.stdout);
+    Ok(secret.strip_suffix('\n').unwrap_or(&secret).trim_end_matches('\r').to_owned())
+}
+
+/// A file only readable by the current user holding a secret, removed when
+/// dropped unless kept.
+#[derive(Debug)]
+pub struct SecretFile {
+    path: PathBuf,
+}
+
+impl SecretFile {
+    pub fn create(context: &Context, name: &str, contents: &str) -> Result<Self, Error> {
+        Self::create_with_mode(context.control_path_directory().join(name), contents, 0o600)
+    }
+
+    fn create_with_mode(path: PathBuf, contents: &str, mode: u32) -> Result<Self, Error> {
+        // the mode only applies to new files
+        let _unused = std::fs::remove_file(&path);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(mode)
+            .open(&path)
+            .with_context(|_| error::WriteSecretFileSnafu { file_path: path.clone() })?;
+        let secret_file = Self { path };
+        file.write_all(contents.as_bytes()).with_context(|_| error::WriteSecretFileSnafu {
+            file_path: secret_file.path.clone(),
+        })?;
+        Ok(secret_file)
+    }
+
+    #[inline]
+    pub fn path(&self) -> &Path { &self.path }
+
+    /// Leaves the file in place, e.g. for a container that reads it again when
+    /// restarted, until it is removed with [`SecretFile::remove`].
+    pub fn keep(mut self) -> PathBuf { std::mem::take(&mut self.path) }
+
+    /// Removes a file created by [`SecretFile::create`] and kept, if any.
+    pub fn remove(context: &Context, name: &str) {
+        let path = context.control_path_directory().join(name);
+        match std::fs::remove_file(&path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                tracing::warn!("Could not remove secret file {}, error: {err}", path.display());
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Drop for SecretFile {
+    fn drop(&mut self) {
+        if self.path.as_os_str().is_empty() {
+            return;
+        }
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            tracing::warn!("Could not remove secret file {}, error: {err}", self.path.display());
+        }
+    }
+}
+
+/// Returns the name of the file holding the credentials mounted into the
+/// container of `tunnel`, which may only exist as long as the container.
+pub fn credentials_file_name(tunnel: &str) -> String { format!("{tunnel}.credentials") }
+
+/// A passphrase handed to ssh through `SSH_ASKPASS`.
+#[derive(Debug)]
+pub struct Askpass {
+    passphrase: SecretFile,
+    script: SecretFile,
+}
+
+impl Askpass {
+    pub fn create(context: &Context, name: &str, passphrase: &str) -> Result<Self, Error> {
+        let passphrase = SecretFile::create(context, &format!("{name}.passphrase"), passphrase)?;
+        // the script reads the file named by the environment, so that its path needs
+        // no quoting, and answers only the passphrase prompt, not e.g. the one
+        // asking to accept an unknown host key
+        let script = SecretFile::create_with_mode(
+            context.control_path_directory().join(format!("{name}.askpass")),
+            "#!/bin/sh\ncase \"$1\" in\n*passphrase*) exec cat \"$TUNKA_PASSPHRASE_FILE\" \
+             ;;\nesac\nexit 1\n",
+            0o700,
+        )?;
+        Ok(Self { passphrase, script })
+    }
+
+    /// Returns the environment variables making ssh ask the script.
+    pub fn envs(&self) -> [(&str, &Path); 3] {
+        [
+            ("SSH_ASKPASS", self.script.path()),
+            ("SSH_ASKPASS_REQUIRE", Path::new("force")),
+            ("TUNKA_PASSPHRASE_FILE", self.passphrase.path()),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    use crate::tunnel::test_util::{write_script, TempDir};
+
+    fn context() -> Context {
+        let dir = std::env::temp_dir().join(format!("tunka-secret-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Context::new("the-user", "/home/the-user", dir)
+    }
+
+    #[test]
+    fn test_resolve() {
+        let context = context();
+        let secret = SecretSource::Command("printf 'the-user\\nhunter2\\n'".to_owned());
+        assert_eq!(secret.resolve(&context).unwrap(), "the-user\nhunter2");
+
+        let secret = SecretSource::Command("echo $USER".to_owned());
+        assert_eq!(secret.resolve(&context).unwrap(), "the-user");
+
+        let err = SecretSource::Command("exit 3".to_owned()).resolve(&context).unwrap_err();
+        assert!(matches!(err, Error::ExternalCommand { code: 3, .. }));
+
+        let secret = SecretSource::Env("TUNKA_TEST_SECRET_MISSING".to_owned());
+        assert!(matches!(
+            secret.resolve(&context),
+            Err(Error::SecretEnvironmentVariableNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_secret_service() {
+        let dir = TempDir::new("secret-service");
+        let stand_in = write_script(
+            dir.path(),
+            "secret-tool",
+            "[ \"$*\" = 'lookup service vpn user work' ] && echo hunter2",
+        );
+        let attributes = BTreeMap::from([
+            ("service".to_owned(), "vpn".to_owned()),
+            ("user".to_owned(), "work".to_owned()),
+        ]);
+
+        let program = stand_in.to_string_lossy();
+        assert_eq!(lookup_secret_service(&program, &attributes).unwrap(), "hunter2");
+        assert!(lookup_secret_service(&program, &BTreeMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_askpass() {
+        let context = context();
+        let askpass = Askpass::create(&context, "askpass-test", "hunter2").unwrap();
+        let ask = |prompt: &str| {
+            Command::new(askpass.script.path()).arg(prompt).envs(askpass.envs()).output().unwrap()
+        };
+
+        let output = ask("Enter passphrase for key '/home/the-user/.ssh/id_ed25519': ");
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "hunter2");
+
+        let output = ask("Are you sure you want to continue connecting (yes/no/[fingerprint])? ");
+        assert!(!output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+    }
+
+    #[test]
+    fn test_secret_file() {
+        let context = context();
+        let secret_file = SecretFile::create(&context, "vpn.auth", "the-user\nhunter2\n").unwrap();
+        let path = secret_file.path().to_owned();
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "the-user\nhunter2\n");
+
+        drop(secret_file);
+        assert!(!path.exists());
+
+        let kept = SecretFile::create(&context, "vpn.kept", "hunter2\n").unwrap().keep();
+        assert!(kept.exists());
+        SecretFile::remove(&context, "vpn.kept");
+        assert!(!kept.exists());
+        SecretFile::remove(&context, "vpn.kept");
+    }
+}
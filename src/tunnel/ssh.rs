@@ -1,44 +1,329 @@
 use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::File,
     path::PathBuf,
-    process::{Command, Stdio},
+    process::{Command, ExitStatus, Stdio},
 };
 
+use serde::{Deserialize, Serialize};
 use snafu::ResultExt;
 
 use crate::{
     context::Context,
     error,
     error::Error,
-    tunnel::{Tunnel, TunnelMeta, TunnelType},
+    hash,
+    tunnel::{log_file, secret::Askpass, SecretSource, Tunnel, TunnelMeta, TunnelType},
 };
 
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum SshForwardTarget {
+    Address { target_host: String, target_port: u16 },
+    UnixSocket { target_socket: PathBuf },
+}
+
+impl fmt::Display for SshForwardTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Address { target_host, target_port } => {
+                write!(f, "{}:{target_port}", bracket_host(target_host))
+            }
+            Self::UnixSocket { target_socket } => write!(f, "{}", target_socket.display()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct SshLocalForward {
+    pub listen_host: String,
+    pub listen_port: u16,
+    #[serde(flatten)]
+    pub target: SshForwardTarget,
+}
+
+impl SshLocalForward {
+    #[inline]
+    pub fn to_arg(&self) -> String {
+        format!("{}:{}:{}", bracket_host(&self.listen_host), self.listen_port, self.target)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct SshRemoteForward {
+    #[serde(default)]
+    pub listen_host: Option<String>,
+    pub listen_port: u16,
+    #[serde(flatten)]
+    pub target: SshForwardTarget,
+}
+
+impl SshRemoteForward {
+    #[inline]
+    pub fn to_arg(&self) -> String {
+        let Self { listen_host, listen_port, target } = self;
+        listen_host.as_deref().map_or_else(
+            || format!("{listen_port}:{target}"),
+            |listen_host| format!("{}:{listen_port}:{target}", bracket_host(listen_host)),
+        )
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct SshJumpHost {
+    pub host: String,
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub user_name: Option<String>,
+    #[serde(default)]
+    pub identify_file: Option<PathBuf>,
+}
+
+impl SshJumpHost {
+    /// Returns the jump host in `[user@]host[:port]` form.
+    pub fn destination(&self) -> String {
+        let user = self.user_name.as_ref().map(|u| format!("{u}@")).unwrap_or_default();
+        self.port.map_or_else(
+            || format!("{user}{}", self.host),
+            |port| format!("{user}{}:{port}", bracket_host(&self.host)),
+        )
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct SshTunnel {
     pub meta: TunnelMeta,
     pub remote_host: String,
-    pub remote_port: u16,
-    pub user_name: String,
-    pub identify_file: PathBuf,
+    pub remote_port: Option<u16>,
+    pub user_name: Option<String>,
+    pub identify_file: Option<PathBuf>,
+    /// Passphrase of `identify_file`, handed to ssh through `SSH_ASKPASS`.
+    pub identity_passphrase: Option<SecretSource>,
+    pub ssh_config: Option<PathBuf>,
     pub listen_host: String,
     pub listen_port: u16,
+    pub local_forwards: Vec<SshLocalForward>,
+    pub remote_forwards: Vec<SshRemoteForward>,
+    pub jump_hosts: Vec<SshJumpHost>,
+    pub ssh_options: BTreeMap<String, String>,
+    pub extra_args: Vec<String>,
 }
 
 impl SshTunnel {
+    /// Returns the socket of the master connection. The destination is hashed
+    /// into the file name, which has to fit into `sun_path` along with the
+    /// suffix ssh appends while binding.
     #[inline]
     pub fn control_path(&self, context: &Context) -> PathBuf {
         PathBuf::from_iter([
             context.control_path_directory(),
             PathBuf::from({
                 let tunnel_name = self.name();
-                let Self { user_name, remote_host, remote_port, .. } = self;
-                format!("{tunnel_name}_{user_name}@{remote_host}:{remote_port}.socket")
+                let user = self.user_name.as_ref().map(|u| format!("{u}@")).unwrap_or_default();
+                let remote_host = &self.remote_host;
+                let port = self.remote_port.map(|p| format!(":{p}")).unwrap_or_default();
+                let via = if self.jump_hosts.is_empty() {
+                    String::new()
+                } else {
+                    format!("_via_{}", self.jump_chain())
+                };
+                let destination = format!("{user}{remote_host}{port}{via}");
+                format!("{tunnel_name}_{:016x}.socket", hash::fnv1a(destination.as_bytes()))
             }),
         ])
     }
 
+    fn jump_chain(&self) -> String {
+        self.jump_hosts.iter().map(SshJumpHost::destination).collect::<Vec<_>>().join(",")
+    }
+
+    pub fn jump_args(&self, context: &Context) -> Vec<String> {
+        if self.jump_hosts.is_empty() {
+            Vec::new()
+        } else if self.jump_hosts.iter().all(|jump| jump.identify_file.is_none()) {
+            vec!["-J".to_owned(), self.jump_chain()]
+        } else {
+            // `ProxyJump` can not carry an identity file for each hop, fall back to a
+            // chain of nested `ProxyCommand`s instead
+            vec![
+                "-o".to_owned(),
+                format!("ProxyCommand={}", proxy_command(&self.jump_hosts, context)),
+            ]
+        }
+    }
+
+    /// Returns the file receiving the stderr of the ssh process.
+    #[inline]
+    pub fn log_file(&self, context: &Context) -> PathBuf {
+        context.control_path_directory().join(format!("{}.log", self.name()))
+    }
+
     pub fn control_path_option(&self, context: &Context) -> String {
         format!("ControlPath={path}", path = self.control_path(context).to_string_lossy())
     }
+
+    fn config_args(&self, context: &Context) -> Vec<String> {
+        self.ssh_config.as_ref().map_or_else(Vec::new, |ssh_config| {
+            vec!["-F".to_owned(), context.apply_path(ssh_config).to_string_lossy().into_owned()]
+        })
+    }
+
+    /// Options left out are resolved by ssh from its configuration files, which
+    /// allows `remote_host` to be a `Host` alias.
+    fn connection_args(&self, context: &Context) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(identify_file) = &self.identify_file {
+            args.push("-i".to_owned());
+            args.push(context.apply_path(identify_file).to_string_lossy().into_owned());
+        }
+        if let Some(user_name) = &self.user_name {
+            args.push("-l".to_owned());
+            args.push(user_name.clone());
+        }
+        if let Some(remote_port) = self.remote_port {
+            args.push("-p".to_owned());
+            args.push(remote_port.to_string());
+        }
+        args
+    }
+
+    /// Returns `ssh_options` as `-o Key=Value` pairs, restricted to the options
+    /// which also affect control commands when `control_only` is set.
+    fn option_args(&self, control_only: bool) -> Vec<String> {
+        self.ssh_options
+            .iter()
+            .filter(|(key, _)| {
+                !control_only
+                    || CONTROL_OPTIONS.iter().any(|option| option.eq_ignore_ascii_case(key))
+            })
+            .flat_map(|(key, value)| ["-o".to_owned(), format!("{key}={value}")])
+            .collect()
+    }
+
+    pub fn start_args(&self, context: &Context) -> Vec<String> {
+        let mut args = self.config_args(context);
+        args.extend([
+            "-o".to_owned(),
+            self.control_path_option(context),
+            "-o".to_owned(),
+            "ControlMaster=auto".to_owned(),
+            "-f".to_owned(),
+            "-N".to_owned(),
+        ]);
+        // ssh uses the first value obtained for each option, so user provided options
+        // can not override the control options above
+        args.extend(self.option_args(false));
+        // treat forwards which can not be set up as a failure unless told otherwise
+        if !self.ssh_options.keys().any(|key| key.eq_ignore_ascii_case("ExitOnForwardFailure")) {
+            args.extend(["-o".to_owned(), "ExitOnForwardFailure=yes".to_owned()]);
+        }
+        args.extend(self.forward_args());
+        args.extend(self.jump_args(context));
+        args.extend(self.connection_args(context));
+        args.extend(self.extra_args.iter().cloned());
+        args.push(self.remote_host.clone());
+        args
+    }
+
+    pub fn control_args(&self, context: &Context, command: &str) -> Vec<String> {
+        let mut args = vec!["-O".to_owned(), command.to_owned()];
+        args.extend(self.config_args(context));
+        args.extend(["-o".to_owned(), self.control_path_option(context)]);
+        args.extend(self.option_args(true));
+        args.push(self.remote_host.clone());
+        args
+    }
+
+    pub fn forward_args(&self) -> Vec<String> {
+        let mut args = vec![
+            "-D".to_owned(),
+            format!("{}:{}", bracket_host(&self.listen_host), self.listen_port),
+        ];
+        for forward in &self.local_forwards {
+            args.push("-L".to_owned());
+            args.push(forward.to_arg());
+        }
+        for forward in &self.remote_forwards {
+            args.push("-R".to_owned());
+            args.push(forward.to_arg());
+        }
+        args
+    }
+}
+
+// Options passed along to `ssh -O`, the others only matter when establishing
+// the master connection.
+const CONTROL_OPTIONS: &[&str] =
+    &["BatchMode", "ConnectTimeout", "Hostname", "LogLevel", "Port", "User"];
+
+// Builds a `ProxyCommand` reaching the last host of `jump_hosts` through all
+// the hosts before it. Every nesting level is expanded once more by ssh, so `%`
+// of the inner command has to be escaped.
+fn proxy_command(jump_hosts: &[SshJumpHost], context: &Context) -> String {
+    let Some((last, rest)) = jump_hosts.split_last() else {
+        return String::new();
+    };
+
+    let mut words = vec!["ssh".to_owned()];
+    if let Some(identify_file) = &last.identify_file {
+        words.push("-i".to_owned());
+        words.push(shell_quote(&context.apply_path(identify_file).to_string_lossy()));
+    }
+    if let Some(user_name) = &last.user_name {
+        words.push("-l".to_owned());
+        words.push(shell_quote(user_name));
+    }
+    if let Some(port) = last.port {
+        words.push("-p".to_owned());
+        words.push(port.to_string());
+    }
+    if !rest.is_empty() {
+        words.push("-o".to_owned());
+        words.push(shell_quote(&format!(
+            "ProxyCommand={}",
+            proxy_command(rest, context).replace('%', "%%")
+        )));
+    }
+    words.push("-W".to_owned());
+    words.push("%h:%p".to_owned());
+    words.push(shell_quote(&last.host));
+    words.join(" ")
+}
+
+// The tunnel is named by `Error::StartTunnel` wrapping this error.
+fn command_error(args: &[String], exit_status: ExitStatus, stderr: &str) -> Error {
+    let stderr = match stderr.trim() {
+        "" => format!("ssh exited with {exit_status}"),
+        stderr => stderr.to_owned(),
+    };
+    Error::SshCommand { command: command_line("ssh", args), stderr }
+}
+
+fn command_line(program: &str, args: &[String]) -> String {
+    std::iter::once(program.to_owned())
+        .chain(args.iter().map(|arg| shell_quote(arg)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn shell_quote(s: &str) -> String {
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || "@%+=:,./_-".contains(c)) {
+        s.to_owned()
+    } else {
+        format!("'{}'", s.replace('\'', r"'\''"))
+    }
+}
+
+// IPv6 literals have to be wrapped in brackets in ssh forwarding
+// specifications.
+fn bracket_host(host: &str) -> String {
+    if host.contains(':') {
+        format!("[{host}]")
+    } else {
+        host.to_owned()
+    }
 }
 
 impl Tunnel for SshTunnel {
@@ -60,54 +345,72 @@ impl Tunnel for SshTunnel {
             return Ok(());
         }
 
-        let _result = Command::new("ssh")
-            .args([
-                "-o",
-                &self.control_path_option(context),
-                "-o",
-                "ControlMaster=auto",
-                "-f",
-                "-N",
-                "-D",
-                &format!("{}:{}", self.listen_host, self.listen_port),
-                "-i",
-                &context.apply_path(&self.identify_file).to_string_lossy(),
-                "-l",
-                &self.user_name,
-                "-p",
-                &format!("{}", self.remote_port),
-                &self.remote_host,
-            ])
+        // `ssh -f` keeps stderr open in the background, capture it into a file
+        // instead of a pipe which would never reach EOF
+        let file_path = self.log_file(context);
+        let log_file = File::create(&file_path)
+            .with_context(|_| error::CreateLogFileSnafu { file_path: file_path.clone() })?;
+
+        let askpass = match &self.identity_passphrase {
+            Some(passphrase) => {
+                Some(Askpass::create(context, self.name(), &passphrase.resolve(context)?)?)
+            }
+            None => None,
+        };
+
+        let args = self.start_args(context);
+        let exit_status = Command::new("ssh")
+            .args(&args)
+            .envs(askpass.iter().flat_map(Askpass::envs))
             .stdin(Stdio::null())
             .stdout(Stdio::null())
-            .stderr(Stdio::null())
+            .stderr(log_file)
             .spawn()
             .with_context(|_| error::SpawnSshCommandSnafu)?
             .wait()
             .with_context(|_| error::WaitForSshProcessSnafu)?;
 
-        Ok(())
+        if exit_status.success() {
+            Ok(())
+        } else {
+            let stderr = std::fs::read_to_string(&file_path).unwrap_or_default();
+            Err(command_error(&args, exit_status, &stderr))
+        }
     }
 
     #[inline]
     fn stop(&self, context: &Context) -> Result<(), Error> {
-        let _result = Command::new("ssh")
-            .args(["-O", "exit", "-o", &self.control_path_option(context), &self.remote_host])
+        let args = self.control_args(context, "exit");
+        let output = Command::new("ssh")
+            .args(&args)
             .stdin(Stdio::null())
             .stdout(Stdio::null())
-            .stderr(Stdio::null())
+            .stderr(Stdio::piped())
             .spawn()
             .with_context(|_| error::SpawnSshCommandSnafu)?
-            .wait()
+            .wait_with_output()
             .with_context(|_| error::WaitForSshProcessSnafu)?;
 
-        Ok(())
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(command_error(&args, output.status, &String::from_utf8_lossy(&output.stderr)))
+        }
+    }
+
+    #[inline]
+    fn logs(&self, context: &Context, follow: bool, tail: Option<usize>) -> Result<(), Error> {
+        log_file::print(&self.log_file(context), follow, tail)
     }
 
+    /// The dynamic forward set up with `-D`.
+    #[inline]
+    fn socks_endpoint(&self) -> Option<(&str, u16)> { Some((&self.listen_host, self.listen_port)) }
+
     #[inline]
     fn is_running(&self, context: &Context) -> Result<bool, Error> {
         let output = Command::new("ssh")
-            .args(["-O", "check", "-o", &self.control_path_option(context), &self.remote_host])
+            .args(self.control_args(context, "check"))
             .stdin(Stdio::null())
             .stdout(Stdio::null())
             .stderr(Stdio::null())
@@ -119,3 +422,308 @@ impl Tunnel for SshTunnel {
         Ok(output.success())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tunnel() -> SshTunnel {
+        SshTunnel {
+            meta: TunnelMeta { name: "ssh-tunnel".to_owned(), description: None },
+            remote_host: "bastion".to_owned(),
+            remote_port: Some(22),
+            user_name: Some("the-user".to_owned()),
+            identify_file: Some("/tmp/id".into()),
+            identity_passphrase: None,
+            ssh_config: None,
+            listen_host: "127.0.0.1".to_owned(),
+            listen_port: 1080,
+            local_forwards: Vec::new(),
+            remote_forwards: Vec::new(),
+            jump_hosts: Vec::new(),
+            ssh_options: BTreeMap::new(),
+            extra_args: Vec::new(),
+        }
+    }
+
+    fn context() -> Context { Context::new("the-user", "/home/the-user", "/tmp/tunka") }
+
+    #[test]
+    fn test_forward_args() {
+        let tunnel = SshTunnel {
+            local_forwards: vec![
+                SshLocalForward {
+                    listen_host: "127.0.0.1".to_owned(),
+                    listen_port: 5432,
+                    target: SshForwardTarget::Address {
+                        target_host: "db.internal".to_owned(),
+                        target_port: 5432,
+                    },
+                },
+                SshLocalForward {
+                    listen_host: "::1".to_owned(),
+                    listen_port: 6379,
+                    target: SshForwardTarget::UnixSocket {
+                        target_socket: "/run/redis/redis.sock".into(),
+                    },
+                },
+            ],
+            ..tunnel()
+        };
+
+        assert_eq!(
+            tunnel.forward_args(),
+            [
+                "-D",
+                "127.0.0.1:1080",
+                "-L",
+                "127.0.0.1:5432:db.internal:5432",
+                "-L",
+                "[::1]:6379:/run/redis/redis.sock",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_remote_forward_args() {
+        let tunnel = SshTunnel {
+            remote_forwards: vec![
+                SshRemoteForward {
+                    listen_host: None,
+                    listen_port: 8080,
+                    target: SshForwardTarget::Address {
+                        target_host: "127.0.0.1".to_owned(),
+                        target_port: 3000,
+                    },
+                },
+                SshRemoteForward {
+                    listen_host: Some("0.0.0.0".to_owned()),
+                    listen_port: 9000,
+                    target: SshForwardTarget::UnixSocket { target_socket: "/tmp/app.sock".into() },
+                },
+            ],
+            ..tunnel()
+        };
+
+        assert_eq!(
+            tunnel.forward_args(),
+            [
+                "-D",
+                "127.0.0.1:1080",
+                "-R",
+                "8080:127.0.0.1:3000",
+                "-R",
+                "0.0.0.0:9000:/tmp/app.sock",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_jump_args() {
+        let context = context();
+        let jump_hosts = vec![
+            SshJumpHost {
+                host: "bastion-1".to_owned(),
+                port: None,
+                user_name: Some("alice".to_owned()),
+                identify_file: None,
+            },
+            SshJumpHost {
+                host: "bastion-2".to_owned(),
+                port: Some(2222),
+                user_name: None,
+                identify_file: None,
+            },
+        ];
+        assert!(tunnel().control_path(&context).ends_with("ssh-tunnel_4a95f4294027b956.socket"));
+
+        let tunnel = SshTunnel { jump_hosts, ..tunnel() };
+        assert_eq!(tunnel.jump_args(&context), ["-J", "alice@bastion-1,bastion-2:2222"]);
+        assert!(tunnel.control_path(&context).ends_with("ssh-tunnel_5a078fc7ac44e76f.socket"));
+
+        // long host names of every hop still fit into `sun_path`
+        let jump_hosts = (0..8)
+            .map(|i| SshJumpHost {
+                host: format!("bastion-{i}.eu-central-1.compute.internal.example.com"),
+                port: Some(2222),
+                user_name: Some("the-user".to_owned()),
+                identify_file: None,
+            })
+            .collect();
+        let long_tunnel = SshTunnel { jump_hosts, ..tunnel };
+        assert!(long_tunnel.control_path(&context).as_os_str().len() < 64);
+    }
+
+    #[test]
+    fn test_jump_args_with_identify_files() {
+        let jump_hosts = vec![
+            SshJumpHost {
+                host: "bastion-1".to_owned(),
+                port: None,
+                user_name: Some("alice".to_owned()),
+                identify_file: Some("/keys/one".into()),
+            },
+            SshJumpHost {
+                host: "bastion-2".to_owned(),
+                port: Some(2222),
+                user_name: None,
+                identify_file: Some("/keys/two".into()),
+            },
+        ];
+        let tunnel = SshTunnel { jump_hosts, ..tunnel() };
+
+        assert_eq!(
+            tunnel.jump_args(&context()),
+            [
+                "-o",
+                "ProxyCommand=ssh -i /keys/two -p 2222 -o 'ProxyCommand=ssh -i /keys/one -l alice \
+                 -W %%h:%%p bastion-1' -W %h:%p bastion-2",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_start_args() {
+        let context = context();
+        assert_eq!(
+            tunnel().start_args(&context),
+            [
+                "-o",
+                "ControlPath=/tmp/tunka/ssh-tunnel_4a95f4294027b956.socket",
+                "-o",
+                "ControlMaster=auto",
+                "-f",
+                "-N",
+                "-o",
+                "ExitOnForwardFailure=yes",
+                "-D",
+                "127.0.0.1:1080",
+                "-i",
+                "/tmp/id",
+                "-l",
+                "the-user",
+                "-p",
+                "22",
+                "bastion",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_start_args_with_host_alias() {
+        let context = context();
+        let tunnel = SshTunnel {
+            remote_host: "prod".to_owned(),
+            remote_port: None,
+            user_name: None,
+            identify_file: None,
+            ssh_config: Some("$HOME/.ssh/work_config".into()),
+            ..tunnel()
+        };
+
+        assert_eq!(
+            tunnel.start_args(&context),
+            [
+                "-F",
+                "/home/the-user/.ssh/work_config",
+                "-o",
+                "ControlPath=/tmp/tunka/ssh-tunnel_85729c0e3537be14.socket",
+                "-o",
+                "ControlMaster=auto",
+                "-f",
+                "-N",
+                "-o",
+                "ExitOnForwardFailure=yes",
+                "-D",
+                "127.0.0.1:1080",
+                "prod",
+            ]
+        );
+        assert_eq!(
+            tunnel.control_args(&context, "check"),
+            [
+                "-O",
+                "check",
+                "-F",
+                "/home/the-user/.ssh/work_config",
+                "-o",
+                "ControlPath=/tmp/tunka/ssh-tunnel_85729c0e3537be14.socket",
+                "prod",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_start_args_with_ssh_options() {
+        let context = context();
+        let tunnel = SshTunnel {
+            ssh_options: BTreeMap::from([
+                ("ExitOnForwardFailure".to_owned(), "yes".to_owned()),
+                ("LogLevel".to_owned(), "ERROR".to_owned()),
+                ("ServerAliveInterval".to_owned(), "30".to_owned()),
+            ]),
+            extra_args: vec!["-C".to_owned(), "-4".to_owned()],
+            ..tunnel()
+        };
+
+        assert_eq!(
+            tunnel.start_args(&context),
+            [
+                "-o",
+                "ControlPath=/tmp/tunka/ssh-tunnel_4a95f4294027b956.socket",
+                "-o",
+                "ControlMaster=auto",
+                "-f",
+                "-N",
+                "-o",
+                "ExitOnForwardFailure=yes",
+                "-o",
+                "LogLevel=ERROR",
+                "-o",
+                "ServerAliveInterval=30",
+                "-D",
+                "127.0.0.1:1080",
+                "-i",
+                "/tmp/id",
+                "-l",
+                "the-user",
+                "-p",
+                "22",
+                "-C",
+                "-4",
+                "bastion",
+            ]
+        );
+        assert_eq!(
+            tunnel.control_args(&context, "exit"),
+            [
+                "-O",
+                "exit",
+                "-o",
+                "ControlPath=/tmp/tunka/ssh-tunnel_4a95f4294027b956.socket",
+                "-o",
+                "LogLevel=ERROR",
+                "bastion",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_command_error() {
+        let error = command_error(
+            &[
+                "-O".to_owned(),
+                "exit".to_owned(),
+                "-o".to_owned(),
+                "ControlPath=/tmp/a b".to_owned(),
+            ],
+            ExitStatus::default(),
+            "Control socket connect(/tmp/a b): No such file or directory\n",
+        );
+        assert_eq!(
+            error.to_string(),
+            "SSH command `ssh -O exit -o 'ControlPath=/tmp/a b'` failed, error: Control socket \
+             connect(/tmp/a b): No such file or directory"
+        );
+    }
+}
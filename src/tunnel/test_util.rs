@@ -0,0 +1,36 @@
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+/// A directory of a single test, removed with its contents when dropped.
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("tunka-{name}-{}", std::process::id()));
+        let _unused = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+
+    #[inline]
+    pub fn path(&self) -> &Path { &self.path }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) { let _unused = std::fs::remove_dir_all(&self.path); }
+}
+
+/// Writes an executable shell script standing in for a program.
+pub fn write_script(dir: &Path, name: &str, body: &str) -> PathBuf {
+    let path = dir.join(name);
+    let mut file = OpenOptions::new().write(true).create_new(true).mode(0o700).open(&path).unwrap();
+    file.write_all(format!("#!/bin/sh\n{body}\n").as_bytes()).unwrap();
+    path
+}
@@ -0,0 +1,475 @@
+use std::{
+    cell::Cell,
+    collections::BTreeMap,
+    fs::{DirBuilder, OpenOptions},
+    io::{Read, Write},
+    os::unix::{
+        fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt},
+        net::UnixStream,
+    },
+    path::{Path, PathBuf},
+    process::Command,
+    thread,
+    time::{Duration, Instant},
+};
+
+use snafu::ResultExt;
+
+use crate::{
+    context::Context,
+    error::{self, Error},
+    tunnel::{
+        docker::{ContainerSpec, ContainerState, DockerMount},
+        log_file,
+        process::BackgroundProcess,
+        DockerTunnel, Tunnel, TunnelMeta, TunnelType,
+    },
+};
+
+const BOOTSTRAP_INTERVAL: Duration = Duration::from_millis(500);
+
+const CONTAINER_CONFIG_FILE: &str = "/etc/tor/torrc";
+
+const CONTAINER_DATA_DIRECTORY: &str = "/var/lib/tor";
+
+/// Runs a tor client exposing its SOCKS port, either as a native daemon or in
+/// a container running `tor -f /etc/tor/torrc`. Either way the data directory
+/// lives under the control path directory, the container runs as its owner
+/// unless it sets a user.
+#[derive(Clone, Debug)]
+pub struct TorTunnel {
+    pub meta: TunnelMeta,
+    pub listen_host: String,
+    pub listen_port: u16,
+    /// `Bridge` lines, e.g. `obfs4 192.0.2.1:443 <fingerprint> cert=...
+    /// iat-mode=0`.
+    pub bridges: Vec<String>,
+    /// `ClientTransportPlugin` lines, e.g. `obfs4 exec /usr/bin/obfs4proxy`.
+    pub transport_plugins: Vec<String>,
+    /// Country codes the exit relays are restricted to.
+    pub exit_countries: Vec<String>,
+    pub tor_options: BTreeMap<String, String>,
+    pub bootstrap_timeout: Duration,
+    pub container: Option<DockerTunnel>,
+}
+
+impl TorTunnel {
+    /// Port of the SOCKS proxy inside the container.
+    pub const CONTAINER_SOCKS_PORT: u16 = 9050;
+    pub const DEFAULT_BOOTSTRAP_TIMEOUT: Duration = Duration::from_secs(120);
+
+    /// Returns the `DataDirectory` of tor, under the control path directory.
+    #[inline]
+    pub fn data_directory(&self, context: &Context) -> PathBuf {
+        context.control_path_directory().join(format!("{}.tor", self.name()))
+    }
+
+    #[inline]
+    pub fn config_file(&self, context: &Context) -> PathBuf {
+        context.control_path_directory().join(format!("{}.torrc", self.name()))
+    }
+
+    #[inline]
+    pub fn log_file(&self, context: &Context) -> PathBuf {
+        context.control_path_directory().join(format!("{}.log", self.name()))
+    }
+
+    #[inline]
+    pub fn pid_file(&self, context: &Context) -> PathBuf {
+        context.control_path_directory().join(format!("{}.pid", self.name()))
+    }
+
+    fn process(&self, context: &Context) -> BackgroundProcess {
+        BackgroundProcess {
+            program: "tor".to_owned(),
+            args: vec!["-f".to_owned(), self.config_file(context).to_string_lossy().into_owned()],
+            pid_file: self.pid_file(context),
+            log_file: self.log_file(context),
+        }
+    }
+
+    pub fn torrc(&self, context: &Context) -> String {
+        let mut lines = Vec::new();
+        if self.container.is_some() {
+            lines.push(format!("DataDirectory {CONTAINER_DATA_DIRECTORY}"));
+            lines.push(format!("SocksPort 0.0.0.0:{}", Self::CONTAINER_SOCKS_PORT));
+        } else {
+            let data_directory = self.data_directory(context);
+            lines.push(format!("DataDirectory {}", quote(&data_directory)));
+            lines.push(format!("SocksPort {}:{}", self.listen_host, self.listen_port));
+            lines.push(format!("ControlSocket {}", quote(&data_directory.join("control.sock"))));
+            lines.push("CookieAuthentication 1".to_owned());
+        }
+
+        if !self.bridges.is_empty() {
+            lines.push("UseBridges 1".to_owned());
+            lines.extend(self.bridges.iter().map(|bridge| format!("Bridge {bridge}")));
+        }
+        lines.extend(
+            self.transport_plugins.iter().map(|plugin| format!("ClientTransportPlugin {plugin}")),
+        );
+        if !self.exit_countries.is_empty() {
+            let countries = self
+                .exit_countries
+                .iter()
+                .map(|country| format!("{{{}}}", country.to_lowercase()))
+                .collect::<Vec<_>>();
+            lines.push(format!("ExitNodes {}", countries.join(",")));
+            lines.push("StrictNodes 1".to_owned());
+        }
+        lines.extend(self.tor_options.iter().map(|(key, value)| format!("{key} {value}")));
+
+        lines.push(String::new());
+        lines.join("\n")
+    }
+
+    fn prepare(&self, context: &Context) -> Result<(), Error> {
+        let dir_path = self.data_directory(context);
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&dir_path)
+            .with_context(|_| error::CreateDataDirectorySnafu { dir_path: dir_path.clone() })?;
+        // tor refuses data directories accessible by others
+        std::fs::set_permissions(&dir_path, std::fs::Permissions::from_mode(0o700))
+            .with_context(|_| error::CreateDataDirectorySnafu { dir_path })?;
+
+        // the configuration may list bridges, only a container needs to read it as
+        // another user
+        let mode = if self.container.is_some() { 0o644 } else { 0o600 };
+        let file_path = self.config_file(context);
+        let _unused = std::fs::remove_file(&file_path);
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(mode)
+            .open(&file_path)
+            .and_then(|mut file| file.write_all(self.torrc(context).as_bytes()))
+            .with_context(|_| error::WriteConfigFileSnafu { file_path })
+    }
+
+    /// Runs tor in the foreground of a background process, tracked like the
+    /// other processes of tunka.
+    fn start_native(&self, context: &Context) -> Result<(), Error> {
+        let process = self.process(context);
+        if process.is_running() {
+            return self.wait_for_bootstrap(context);
+        }
+
+        let mut child = process.spawn(&mut Command::new(&process.program))?;
+        let progress = Cell::new(0);
+        process
+            .wait_until_ready(self.name(), &mut child, self.bootstrap_timeout, |_log| {
+                // the control socket may not be up yet right after tor is started
+                progress.set(self.bootstrap_progress(context).unwrap_or_default());
+                progress.get() >= 100
+            })
+            .map_err(|err| match err {
+                Error::ProcessNotReady { tunnel } => {
+                    Error::TorBootstrapTimeout { tunnel, progress: progress.get() }
+                }
+                err => err,
+            })
+    }
+
+    fn container_spec(
+        &self,
+        context: &Context,
+        container: &DockerTunnel,
+    ) -> Result<ContainerSpec, Error> {
+        let data_directory = self.data_directory(context);
+        let mounts = [
+            DockerMount {
+                host_endpoint: self.config_file(context),
+                container_endpoint: PathBuf::from(CONTAINER_CONFIG_FILE),
+                read_only: true,
+            },
+            DockerMount {
+                host_endpoint: data_directory.clone(),
+                container_endpoint: PathBuf::from(CONTAINER_DATA_DIRECTORY),
+                read_only: false,
+            },
+        ];
+        let mut spec = container.container_spec(context, &mounts)?;
+        spec.command = vec!["tor".to_owned(), "-f".to_owned(), CONTAINER_CONFIG_FILE.to_owned()];
+        if spec.user.is_none() {
+            // only the owner may access the data directory, see `prepare`
+            let metadata = std::fs::metadata(&data_directory).with_context(|_| {
+                error::CreateDataDirectorySnafu { dir_path: data_directory.clone() }
+            })?;
+            spec.user = Some(format!("{}:{}", metadata.uid(), metadata.gid()));
+        }
+        Ok(spec)
+    }
+
+    fn wait_for_bootstrap(&self, context: &Context) -> Result<(), Error> {
+        let deadline = Instant::now() + self.bootstrap_timeout;
+        loop {
+            // the control socket may not be up yet right after tor is started
+            let progress = self.bootstrap_progress(context).unwrap_or_default();
+            if progress >= 100 {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                // a tor stuck in its bootstrap would keep the port
+                if let Err(err) = self.stop(context) {
+                    tracing::warn!("Could not stop tor of tunnel {}, error: {err}", self.name());
+                }
+                return Err(Error::TorBootstrapTimeout {
+                    tunnel: self.name().to_owned(),
+                    progress,
+                });
+            }
+            thread::sleep(BOOTSTRAP_INTERVAL);
+        }
+    }
+
+    /// Returns the percentage of the bootstrap of tor.
+    pub fn bootstrap_progress(&self, context: &Context) -> Result<u8, Error> {
+        let progress = if let Some(container) = &self.container {
+            parse_bootstrap_log(&container.log_output()?)
+        } else {
+            parse_bootstrap_phase(&self.control(context, "GETINFO status/bootstrap-phase")?)
+        };
+        Ok(progress.unwrap_or_default())
+    }
+
+    /// Checks that tor runs, whether or not it has finished its bootstrap.
+    fn is_process_running(&self, context: &Context) -> Result<bool, Error> {
+        match &self.container {
+            Some(container) => Ok(container.state()? == ContainerState::Running),
+            None => Ok(self.process(context).is_running()),
+        }
+    }
+
+    /// Sends `command` over the control socket, authenticated with the cookie
+    /// in the data directory.
+    fn control(&self, context: &Context, command: &str) -> Result<String, Error> {
+        let data_directory = self.data_directory(context);
+        let cookie_path = data_directory.join("control_auth_cookie");
+        let cookie = std::fs::read(&cookie_path)
+            .with_context(|_| error::ReadTorCookieSnafu { file_path: cookie_path })?;
+        let cookie = cookie.iter().map(|b| format!("{b:02X}")).collect::<Vec<_>>().concat();
+
+        let mut stream = UnixStream::connect(data_directory.join("control.sock"))
+            .context(error::TorControlIoSnafu)?;
+        stream
+            .write_all(format!("AUTHENTICATE {cookie}\r\n{command}\r\nQUIT\r\n").as_bytes())
+            .context(error::TorControlIoSnafu)?;
+        let mut response = String::new();
+        let _unused = stream.read_to_string(&mut response).context(error::TorControlIoSnafu)?;
+
+        if let Some(line) = response.lines().find(|line| !line.starts_with("250")) {
+            return Err(Error::TorControl { message: line.to_owned() });
+        }
+        Ok(response)
+    }
+}
+
+/// Quotes a path in the torrc syntax.
+fn quote(path: &Path) -> String {
+    format!("\"{}\"", path.to_string_lossy().replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Parses the response of `GETINFO status/bootstrap-phase`.
+fn parse_bootstrap_phase(response: &str) -> Option<u8> {
+    let (_, progress) = response.split_once("PROGRESS=")?;
+    progress.split_whitespace().next()?.parse().ok()
+}
+
+/// Finds the last `Bootstrapped 42% (...)` message in the log of tor.
+fn parse_bootstrap_log(log: &str) -> Option<u8> {
+    log.lines().rev().find_map(|line| {
+        let (_, progress) = line.split_once("Bootstrapped ")?;
+        progress.split('%').next()?.parse().ok()
+    })
+}
+
+impl Tunnel for TorTunnel {
+    fn meta(&self) -> &TunnelMeta { &self.meta }
+
+    #[inline]
+    fn tunnel_type(&self) -> TunnelType { TunnelType::Tor }
+
+    fn start(&self, context: &Context) -> Result<(), Error> {
+        if self.is_running(context)? {
+            return Ok(());
+        }
+
+        self.prepare(context)?;
+        match &self.container {
+            Some(container) => {
+                container.start_with_spec(context, &self.container_spec(context, container)?)?;
+                self.wait_for_bootstrap(context)
+            }
+            None => self.start_native(context),
+        }
+    }
+
+    fn stop(&self, context: &Context) -> Result<(), Error> {
+        self.container
+            .as_ref()
+            .map_or_else(|| self.process(context).terminate(), |container| container.stop(context))
+    }
+
+    #[inline]
+    fn pull(&self, context: &Context) -> Result<(), Error> {
+        self.container.as_ref().map_or_else(
+            || {
+                Err(Error::UnsupportedOperation {
+                    tunnel: self.name().to_owned(),
+                    operation: "pull",
+                })
+            },
+            |container| container.pull(context),
+        )
+    }
+
+    #[inline]
+    fn build(&self, context: &Context) -> Result<(), Error> {
+        self.container.as_ref().map_or_else(
+            || {
+                Err(Error::UnsupportedOperation {
+                    tunnel: self.name().to_owned(),
+                    operation: "build",
+                })
+            },
+            |container| container.build(context),
+        )
+    }
+
+    /// Checks that tor runs and has finished its bootstrap, a running tor can
+    /// not build circuits before.
+    fn is_running(&self, context: &Context) -> Result<bool, Error> {
+        Ok(self.is_process_running(context)?
+            && self.bootstrap_progress(context).is_ok_and(|p| p >= 100))
+    }
+
+    fn is_alive(&self, context: &Context) -> Result<bool, Error> {
+        match &self.container {
+            Some(container) => Ok(container.state()? != ContainerState::Missing),
+            None => Ok(self.process(context).is_running()),
+        }
+    }
+
+    fn logs(&self, context: &Context, follow: bool, tail: Option<usize>) -> Result<(), Error> {
+        self.container.as_ref().map_or_else(
+            || log_file::print(&self.log_file(context), follow, tail),
+            |container| container.logs(context, follow, tail),
+        )
+    }
+
+    #[inline]
+    fn container(&self) -> Option<&DockerTunnel> { self.container.as_ref() }
+
+    #[inline]
+    fn socks_endpoint(&self) -> Option<(&str, u16)> { Some((&self.listen_host, self.listen_port)) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tunnel() -> TorTunnel {
+        TorTunnel {
+            meta: TunnelMeta { name: "tor".to_owned(), description: None },
+            listen_host: "127.0.0.1".to_owned(),
+            listen_port: 9150,
+            bridges: vec!["obfs4 192.0.2.1:443 0123456789ABCDEF cert=abc iat-mode=0".to_owned()],
+            transport_plugins: vec!["obfs4 exec /usr/bin/obfs4proxy".to_owned()],
+            exit_countries: vec!["DE".to_owned(), "ch".to_owned()],
+            tor_options: BTreeMap::from([("NumEntryGuards".to_owned(), "3".to_owned())]),
+            bootstrap_timeout: TorTunnel::DEFAULT_BOOTSTRAP_TIMEOUT,
+            container: None,
+        }
+    }
+
+    #[test]
+    fn test_torrc() {
+        let context = Context::new("the-user", "/home/the-user", "/tmp/tunka");
+        assert_eq!(
+            tunnel().torrc(&context),
+            "DataDirectory \"/tmp/tunka/tor.tor\"\nSocksPort 127.0.0.1:9150\nControlSocket \
+             \"/tmp/tunka/tor.tor/control.sock\"\nCookieAuthentication 1\nUseBridges 1\nBridge \
+             obfs4 192.0.2.1:443 0123456789ABCDEF cert=abc iat-mode=0\nClientTransportPlugin \
+             obfs4 exec /usr/bin/obfs4proxy\nExitNodes {de},{ch}\nStrictNodes 1\nNumEntryGuards \
+             3\n"
+        );
+    }
+
+    #[test]
+    fn test_is_alive_while_bootstrapping() {
+        let dir = std::env::temp_dir().join(format!("tunka-tor-{}", std::process::id()));
+        let context = Context::new("the-user", "/home/the-user", &dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let tunnel = tunnel();
+        assert!(!tunnel.is_alive(&context).unwrap());
+
+        // a reused pid is not taken for tor
+        std::fs::write(tunnel.pid_file(&context), format!("{} 1\n", std::process::id())).unwrap();
+        assert!(!tunnel.is_alive(&context).unwrap());
+
+        // stands in for a tor without its control socket yet
+        let process = BackgroundProcess {
+            program: "sleep".to_owned(),
+            args: vec!["30".to_owned()],
+            ..tunnel.process(&context)
+        };
+        let mut child = process.spawn(&mut Command::new("sleep")).unwrap();
+        assert!(tunnel.is_alive(&context).unwrap());
+        assert!(!tunnel.is_running(&context).unwrap());
+
+        tunnel.stop(&context).unwrap();
+        assert!(!tunnel.is_alive(&context).unwrap());
+        let _unused = child.wait();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_container_spec() {
+        let dir = std::env::temp_dir().join(format!("tunka-tor-spec-{}", std::process::id()));
+        let context = Context::new("the-user", "/home/the-user", &dir);
+        let container = DockerTunnel {
+            meta: TunnelMeta { name: "tor".to_owned(), description: None },
+            runtime: crate::tunnel::ContainerRuntime::Docker,
+            engine: None,
+            config_id: String::new(),
+            options: crate::tunnel::ContainerOptions::default(),
+            image_name: "tor".to_owned(),
+            container_name: "tor".to_owned(),
+            container_port: TorTunnel::CONTAINER_SOCKS_PORT,
+            listen_host: "127.0.0.1".to_owned(),
+            listen_port: 9150,
+        };
+        let tunnel = TorTunnel { container: Some(container.clone()), ..tunnel() };
+        tunnel.prepare(&context).unwrap();
+
+        let spec = tunnel.container_spec(&context, &container).unwrap();
+        let data_directory = tunnel.data_directory(&context);
+        assert!(spec.mounts.iter().any(|mount| mount.target == CONTAINER_DATA_DIRECTORY
+            && Path::new(&mount.source) == data_directory
+            && !mount.read_only));
+        let metadata = std::fs::metadata(&data_directory).unwrap();
+        assert_eq!(spec.user, Some(format!("{}:{}", metadata.uid(), metadata.gid())));
+        assert_eq!(spec.command, ["tor", "-f", CONTAINER_CONFIG_FILE]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_bootstrap_progress() {
+        assert_eq!(
+            parse_bootstrap_phase(
+                "250 OK\r\n250-status/bootstrap-phase=NOTICE BOOTSTRAP PROGRESS=100 TAG=done \
+                 SUMMARY=\"Done\"\r\n250 OK\r\n250 closing connection\r\n"
+            ),
+            Some(100)
+        );
+        assert_eq!(parse_bootstrap_phase("250 OK\r\n"), None);
+
+        let log = "[notice] Bootstrapped 0% (starting): Starting\n[notice] Bootstrapped 45% \
+                   (requesting_descriptors): Asking for relay descriptors\n[notice] New control \
+                   connection opened.\n";
+        assert_eq!(parse_bootstrap_log(log), Some(45));
+        assert_eq!(parse_bootstrap_log(""), None);
+    }
+}